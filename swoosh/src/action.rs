@@ -9,6 +9,9 @@ pub enum Action {
     Resize(u16, u16),
     Down,
     Up,
+    Left,
+    Right,
+    FocusNext,
     ShiftUp,
     ShiftDown,
     Select,
@@ -19,9 +22,20 @@ pub enum Action {
     ClearScreen,
     Error(String),
     UpdateImageStatus {
-        index: usize,
+        id: usize,
         status: ImageStatus,
     },
+    UpdateImageDetails {
+        id: usize,
+        details: String,
+    },
+    UpdateImageWarning {
+        id: usize,
+        warning: String,
+    },
     ClearImages,
+    Convert,
+    Optimize,
+    BuildGif,
     Help,
 }
@@ -1,17 +1,32 @@
-use std::path::PathBuf;
+use std::{ path::PathBuf, time::Duration };
 
 use color_eyre::Result;
 use crossterm::event::KeyEvent;
+use image::ImageFormat;
 use ratatui::{ layout::{ Constraint, Direction, Layout }, prelude::Rect };
 use serde::{ Deserialize, Serialize };
-use tokio::sync::mpsc;
-use tracing::{ debug, info };
+use tokio::{ sync::mpsc, task::JoinHandle };
+use tracing::{ debug, info, warn };
 
 use crate::{
     action::Action,
-    components::{ list::ImageList, value::ImageInfo, options::OptionsPanel, Component },
+    components::{
+        image_processing::{
+            animation,
+            compress::EncodeOptions,
+            optimize::optimize_in_place,
+            pipeline::Pipeline,
+        },
+        list::ImageList,
+        options::OptionsPanel,
+        settings::SettingsPanel,
+        value::{ ImageInfo, ImageStatus },
+        Component,
+    },
     config::Config,
+    output::{ Planned, Tokens },
     tui::{ Event, Tui },
+    worker::{ self, ConversionSettings, Converted, Job, Limits },
 };
 
 pub struct App {
@@ -28,6 +43,9 @@ pub struct App {
     left_panel_percentage: u16,
     image_list: ImageList,
     options_panel: OptionsPanel,
+    settings_panel: SettingsPanel<'static>,
+    conversion: Option<JoinHandle<()>>,
+    limits: Limits,
 }
 
 #[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -39,7 +57,8 @@ pub enum Mode {
 #[derive(Debug, Copy, Clone, PartialEq, Eq)] // New: Focus enum
 pub enum Focus {
     ImageList,
-    OptionsPanel, // Add when OptionsPanel is implemented
+    OptionsPanel,
+    SettingsPanel,
 }
 
 impl Default for App {
@@ -59,19 +78,32 @@ impl Default for App {
             left_panel_percentage: 60,
             image_list: ImageList::new(),
             options_panel: OptionsPanel::new(),
+            settings_panel: SettingsPanel::new(),
+            conversion: None,
+            limits: Limits::default(),
         }
     }
 }
 
 impl App {
-    pub fn new(tick_rate: f64, frame_rate: f64, images: Option<Vec<PathBuf>>) -> Result<Self> {
+    pub fn new(
+        tick_rate: f64,
+        frame_rate: f64,
+        images: Option<Vec<PathBuf>>,
+        config: Config,
+        limits: Limits
+    ) -> Result<Self> {
         let mut app = App {
             tick_rate,
             frame_rate,
-            config: Config::new()?,
+            limits,
+            config,
             // options_panel: OptionsPanel::new(),
             ..Default::default()
         };
+        app.settings_panel.resize_options.filter = app.config.config.resize_filter;
+        app.settings_panel.filter3x3_kernel = app.config.config.kernel.kernel();
+        app.options_panel.encoding.alpha = app.config.config.alpha;
         if let Some(images) = images {
             for image_path in images {
                 if let Ok(image_info) = ImageInfo::new(image_path) {
@@ -91,6 +123,10 @@ impl App {
         self.image_list.register_action_handler(self.action_tx.clone())?;
         self.image_list.register_config_handler(self.config.clone())?;
         self.image_list.init(tui.size()?)?;
+        self.options_panel.register_action_handler(self.action_tx.clone())?;
+        self.options_panel.register_config_handler(self.config.clone())?;
+        self.settings_panel.register_action_handler(self.action_tx.clone())?;
+        self.settings_panel.register_config_handler(self.config.clone())?;
 
         let action_tx = self.action_tx.clone();
         loop {
@@ -124,7 +160,12 @@ impl App {
             Event::Key(key) => self.handle_key_event(key)?,
             _ => {}
         }
-        if let Some(action) = self.image_list.handle_events(Some(event.clone()))? {
+        let focused: &mut dyn Component = match self.focused_component {
+            Focus::ImageList => &mut self.image_list,
+            Focus::OptionsPanel => &mut self.options_panel,
+            Focus::SettingsPanel => &mut self.settings_panel,
+        };
+        if let Some(action) = focused.handle_events(Some(event.clone()))? {
             action_tx.send(action)?;
         }
         Ok(())
@@ -176,6 +217,16 @@ impl App {
                 Action::ClearScreen => tui.terminal.clear()?,
                 Action::Resize(w, h) => self.handle_resize(tui, w, h)?,
                 Action::Render => self.render(tui)?,
+                Action::Convert => self.start_conversion(),
+                Action::Optimize => self.start_optimize(),
+                Action::BuildGif => self.start_build_gif(),
+                Action::FocusNext => {
+                    self.focused_component = match self.focused_component {
+                        Focus::ImageList => Focus::OptionsPanel,
+                        Focus::OptionsPanel => Focus::SettingsPanel,
+                        Focus::SettingsPanel => Focus::ImageList,
+                    };
+                }
                 _ => {}
             }
             if let Some(action) = self.image_list.update(action.clone())? {
@@ -185,6 +236,94 @@ impl App {
         Ok(())
     }
 
+    /// Jobs for every queued image, `None` while a conversion is still running or nothing is queued.
+    fn queued_jobs(&self) -> Option<Vec<Job>> {
+        if self.conversion.as_ref().is_some_and(|handle| !handle.is_finished()) {
+            warn!("A conversion is already running");
+            return None;
+        }
+        let jobs: Vec<Job> = self.image_list.image_data
+            .iter()
+            .enumerate()
+            .filter(|(_, image)| image.status == ImageStatus::Queued)
+            .map(|(index, image)| Job { index, id: image.id, input: image.path.clone() })
+            .collect();
+        (!jobs.is_empty()).then_some(jobs)
+    }
+
+    /// Hands every queued image to the background worker unless a conversion is still running.
+    fn start_conversion(&mut self) {
+        let Some(jobs) = self.queued_jobs() else {
+            return;
+        };
+        let inputs: Vec<PathBuf> = jobs.iter().map(|job| job.input.clone()).collect();
+        let settings = ConversionSettings {
+            pipeline: Pipeline::from_settings(&self.settings_panel),
+            encoding: Some(self.options_panel.encoding),
+            alpha: self.config.config.alpha,
+            output: self.config.config.output.clone().for_inputs(&inputs),
+        };
+        info!("Converting {} images", jobs.len());
+        self.conversion = Some(worker::spawn(jobs, settings, self.limits, self.action_tx.clone()));
+    }
+
+    /// Recompresses every queued image in place with the quality and metadata of the options
+    /// panel, showing the savings in its row.
+    fn start_optimize(&mut self) {
+        let Some(jobs) = self.queued_jobs() else {
+            return;
+        };
+        let encoding = self.options_panel.encoding;
+        let options = self.config.config.optimize.clone();
+        info!("Optimizing {} images in place", jobs.len());
+        let task = move |job: &Job| {
+            let optimized = optimize_in_place(&job.input, &encoding, &options)?;
+            Ok(Converted { output: job.input.clone(), details: Some(optimized.summary()), warning: None })
+        };
+        self.conversion = Some(worker::spawn_with(jobs, self.limits, self.action_tx.clone(), task));
+    }
+
+    /// Builds a GIF from the selected images in list order, reported in the row of the first one.
+    fn start_build_gif(&mut self) {
+        if self.conversion.as_ref().is_some_and(|handle| !handle.is_finished()) {
+            warn!("A conversion is already running");
+            return;
+        }
+        let selected = self.image_list.selected();
+        if selected.len() < 2 {
+            warn!("Select at least two images to build a GIF");
+            return;
+        }
+        let inputs: Vec<PathBuf> = selected
+            .iter()
+            .map(|&index| self.image_list.image_data[index].path.clone())
+            .collect();
+        let id = self.image_list.image_data[selected[0]].id;
+        let mut plan = self.config.config.output.clone();
+        plan.template = animation::ANIMATION_TEMPLATE.to_string();
+        let plan = plan.for_inputs(&inputs);
+        let pipeline = Pipeline::from_settings(&self.settings_panel);
+        let encoding = EncodeOptions { format: ImageFormat::Gif, ..self.options_panel.encoding };
+        let job = Job { index: selected[0], id, input: inputs[0].clone() };
+        info!("Building a GIF from {} images", inputs.len());
+        let task = move |job: &Job| {
+            let dimensions = image::image_dimensions(&job.input)?;
+            let tokens = Tokens::new(&job.input, encoding.extension(), dimensions, job.index + 1);
+            let output = match plan.resolve(&job.input, &tokens)? {
+                Planned::Write(output) => output,
+                Planned::Skip(output) => {
+                    let details = Some("skipped, output exists".to_string());
+                    return Ok(Converted { output, details, warning: None });
+                }
+            };
+            let delay = Duration::from_millis(animation::DEFAULT_DELAY_MS);
+            let size = animation::build_animation(&inputs, &pipeline, delay, &output, &encoding)?;
+            let details = format!("GIF of {} frames, {}", inputs.len(), bytesize::ByteSize(size));
+            Ok(Converted { output, details: Some(details), warning: None })
+        };
+        self.conversion = Some(worker::spawn_with(vec![job], self.limits, self.action_tx.clone(), task));
+    }
+
     fn handle_resize(&mut self, tui: &mut Tui, w: u16, h: u16) -> Result<()> {
         tui.resize(Rect::new(0, 0, w, h))?;
         self.render(tui)?;
@@ -200,15 +339,20 @@ impl App {
                     Constraint::Percentage(100 - self.left_panel_percentage),
                 ])
                 .split(frame.area());
+            let right = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .split(chunks[1]);
 
-            let focused = self.focused_component == Focus::ImageList;
             self.image_list
                 .draw(frame, chunks[0], self.focused_component == Focus::ImageList)
                 .unwrap();
-            self.options_panel.draw(frame, chunks[1], focused).unwrap();
-
-            // Render other components (OptionsPanel, FpsCounter) with focus information
-            // ...
+            self.options_panel
+                .draw(frame, right[0], self.focused_component == Focus::OptionsPanel)
+                .unwrap();
+            self.settings_panel
+                .draw(frame, right[1], self.focused_component == Focus::SettingsPanel)
+                .unwrap();
         })?;
         Ok(())
     }
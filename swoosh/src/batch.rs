@@ -0,0 +1,509 @@
+use std::{ path::{ Path, PathBuf }, time::Duration };
+
+use image::{ codecs::avif::ColorSpace, ImageFormat, ImageResult };
+use tracing::{ error, info };
+
+use crate::{
+    cli::{ Command, GifArgs, OutputArgs },
+    components::image_processing::{
+        animation,
+        compress::EncodeOptions,
+        optimize::optimize_in_place,
+        output_set::{ self, OutputSet },
+        pipeline::{ Operation, Pipeline },
+        quantize::QuantizeOptions,
+        transform::{ ResizeMode, ResizeOptions, ResizeSize, RotateOptions },
+    },
+    config::AppConfig,
+    output::OutputPlan,
+    worker::{ self, ConversionSettings, Converted, Job, Limits, Progress },
+};
+
+/// Outcome of a headless batch run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BatchSummary {
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+/// Runs a CLI subcommand over every input file, printing one line per file.
+///
+/// Files are processed concurrently within `limits`, and options left out on the command line
+/// fall back to `config`. A failing file does not stop the batch; it is reported and counted in
+/// the summary.
+pub async fn run(command: Command, config: &AppConfig, limits: Limits) -> BatchSummary {
+    match command {
+        Command::Convert {
+            inputs,
+            format,
+            quality,
+            lossless,
+            speed,
+            avif_rgb,
+            avif_chroma,
+            target_size,
+            metadata,
+            no_auto_orient,
+            tone_map,
+            exposure,
+            alpha,
+            tiff_compression,
+            ico_sizes,
+            pnm,
+            pnm_ascii,
+            no_tga_rle,
+            fast_png,
+            gif,
+            output,
+        } => {
+            let encoding = EncodeOptions {
+                webp_lossless: lossless,
+                avif_speed: speed,
+                avif_color_space: if avif_rgb { ColorSpace::Srgb } else { ColorSpace::Bt709 },
+                avif_chroma,
+                target_size: target_size.map(|size| size.as_u64()),
+                metadata,
+                tone_map,
+                exposure,
+                alpha: alpha.unwrap_or(config.alpha),
+                tiff_compression,
+                ico_sizes,
+                pnm_kind: pnm,
+                pnm_ascii,
+                tga_rle: !no_tga_rle,
+                png_optimize: !fast_png,
+                gif: quantize(gif),
+                ..EncodeOptions::new(format, quality)
+            };
+            let settings = ConversionSettings {
+                pipeline: Pipeline::new().auto_orient(!no_auto_orient),
+                encoding: Some(encoding),
+                alpha: config.alpha,
+                output: plan(output, &config.output, &inputs),
+            };
+            process(inputs, settings, limits).await
+        }
+        Command::Optimize { inputs, quality, min_savings, backup, trash_dir, metadata } => {
+            // The format follows each input
+            let encoding = EncodeOptions { metadata, ..EncodeOptions::new(ImageFormat::Jpeg, quality) };
+            let mut options = config.optimize.clone();
+            options.min_savings = min_savings.unwrap_or(options.min_savings);
+            options.backup = backup.unwrap_or(options.backup);
+            options.trash_dir = trash_dir.or(options.trash_dir);
+            process_with(inputs, PathBuf::new(), limits, move |job| {
+                let optimized = optimize_in_place(&job.input, &encoding, &options)?;
+                Ok(Converted { output: job.input.clone(), details: Some(optimized.summary()), warning: None })
+            }).await
+        }
+        Command::OutputSet { inputs, widths, formats, quality, filter, metadata, output } => {
+            let formats = formats
+                .into_iter()
+                .map(|format| EncodeOptions { metadata, ..EncodeOptions::new(format, quality) })
+                .collect();
+            let mut defaults = config.output.clone();
+            defaults.template = output_set::DEFAULT_TEMPLATE.to_string();
+            let set = OutputSet {
+                filter: filter.unwrap_or(config.resize_filter),
+                plan: plan(output, &defaults, &inputs),
+                ..OutputSet::new(widths, formats)
+            };
+            if let Err(e) = set.validate() {
+                eprintln!("error: {e}");
+                return BatchSummary { succeeded: 0, failed: inputs.len() };
+            }
+            let output_dir = set.plan.dir.clone().unwrap_or_default();
+            process_with(inputs, output_dir, limits, move |job| {
+                let outputs = set.run(&job.input, job.index + 1)?;
+                let files: Vec<String> = outputs
+                    .iter()
+                    .map(|output| {
+                        let name = output.path.file_name().unwrap_or_default().to_string_lossy();
+                        format!("{name} {}", bytesize::ByteSize(output.report.size))
+                    })
+                    .collect();
+                Ok(Converted { output: dir_of(&set.plan, &job.input), details: Some(files.join(", ")), warning: None })
+            }).await
+        }
+        Command::Frames { inputs, format, quality, fast_png, gif, output } => {
+            let encoding = EncodeOptions {
+                png_optimize: !fast_png,
+                alpha: config.alpha,
+                gif: quantize(gif),
+                ..EncodeOptions::new(format, quality)
+            };
+            let mut defaults = config.output.clone();
+            defaults.template = animation::FRAMES_TEMPLATE.to_string();
+            let plan = plan(output, &defaults, &inputs);
+            let output_dir = plan.dir.clone().unwrap_or_default();
+            process_with(inputs, output_dir, limits, move |job| {
+                let frames = animation::extract_frames(&job.input, &Pipeline::new(), &plan, &encoding)?;
+                let details = format!("{} frames", frames.len());
+                Ok(Converted { output: dir_of(&plan, &job.input), details: Some(details), warning: None })
+            }).await
+        }
+        Command::Animate { inputs, output, delay, quality, lossless, gif } => {
+            let format = ImageFormat::from_path(&output).ok().filter(|format| animation::supports_animation(*format));
+            let Some(format) = format else {
+                eprintln!("error: {} is not a GIF, PNG or WebP file", output.display());
+                return BatchSummary { succeeded: 0, failed: 1 };
+            };
+            let encoding = EncodeOptions {
+                webp_lossless: lossless,
+                alpha: config.alpha,
+                gif: quantize(gif),
+                ..EncodeOptions::new(format, quality)
+            };
+            let output_dir = output.parent().map(PathBuf::from).unwrap_or_default();
+            // One job for the whole animation, reported under the first input
+            let first = inputs[..1].to_vec();
+            process_with(first, output_dir, limits, move |_| {
+                let delay = Duration::from_millis(delay);
+                let size = animation::build_animation(&inputs, &Pipeline::new(), delay, &output, &encoding)?;
+                let details = format!("{} frames, {}", inputs.len(), bytesize::ByteSize(size));
+                Ok(Converted { output: output.clone(), details: Some(details), warning: None })
+            }).await
+        }
+        Command::Posterize { inputs, colors, dither, speed, alpha_threshold, format, output } => {
+            let options = QuantizeOptions { colors, speed, dither, alpha_threshold };
+            let output = plan(output, &config.output, &inputs);
+            let settings = single(config, Operation::Posterize { options }, output);
+            // GIF outputs keep the palette the image was just reduced to
+            let encoding = format.map(|format| EncodeOptions {
+                alpha: config.alpha,
+                gif: options,
+                ..EncodeOptions::new(format, 90)
+            });
+            let settings = ConversionSettings { encoding: encoding.or(settings.encoding), ..settings };
+            process(inputs, settings, limits).await
+        }
+        Command::Resize {
+            inputs,
+            width,
+            height,
+            percent,
+            megapixels,
+            mode,
+            filter,
+            exact,
+            background,
+            output,
+        } => {
+            let size = match (percent, megapixels) {
+                (Some(percent), _) => ResizeSize::Percent(percent),
+                (_, Some(megapixels)) => ResizeSize::Megapixels(megapixels),
+                _ => ResizeSize::Pixels { width, height },
+            };
+            let mode = if exact { ResizeMode::Fill } else { mode };
+            let filter = filter.unwrap_or(config.resize_filter);
+            let options = ResizeOptions { filter, background, ..ResizeOptions::new(size, mode) };
+            let output = plan(output, &config.output, &inputs);
+            process(inputs, single(config, Operation::Resize { options }, output), limits).await
+        }
+        Command::Rotate { inputs, degrees, interpolation, keep_size, background, output } => {
+            let options = RotateOptions { interpolation, expand: !keep_size, background };
+            let output = plan(output, &config.output, &inputs);
+            process(inputs, single(config, Operation::Rotate { degrees, options }, output), limits).await
+        }
+        Command::Crop { inputs, aspect, gravity, output } => {
+            let output = plan(output, &config.output, &inputs);
+            process(inputs, single(config, Operation::SmartCrop { aspect, gravity }, output), limits).await
+        }
+        Command::Trim { inputs, tolerance, output } => {
+            let output = plan(output, &config.output, &inputs);
+            process(inputs, single(config, Operation::Trim { tolerance }, output), limits).await
+        }
+        Command::Deskew { inputs, max_angle, output } => {
+            let output = plan(output, &config.output, &inputs);
+            process(inputs, single(config, Operation::Deskew { max_degrees: max_angle }, output), limits).await
+        }
+        Command::Filter { inputs, kernel, output } => {
+            let output = plan(output, &config.output, &inputs);
+            let kernel = kernel.unwrap_or(config.kernel).kernel();
+            process(inputs, single(config, Operation::Filter3x3 { kernel }, output), limits).await
+        }
+    }
+}
+
+/// The configured output plan with the command line overrides applied.
+fn plan(args: OutputArgs, defaults: &OutputPlan, inputs: &[PathBuf]) -> OutputPlan {
+    let mut plan = defaults.clone();
+    plan.dir = args.output_dir.or(plan.dir);
+    plan.template = args.template.unwrap_or(plan.template);
+    plan.collision = args.on_collision.unwrap_or(plan.collision);
+    plan.mirror |= args.mirror;
+    plan.for_inputs(inputs)
+}
+
+/// The GIF palette settings given on the command line.
+fn quantize(args: GifArgs) -> QuantizeOptions {
+    QuantizeOptions {
+        colors: args.gif_colors,
+        speed: args.gif_speed,
+        dither: args.gif_dither,
+        alpha_threshold: args.gif_alpha_threshold,
+    }
+}
+
+/// Directory `plan` writes the outputs of `input` to, ignoring mirroring and template subdirectories.
+fn dir_of(plan: &OutputPlan, input: &Path) -> PathBuf {
+    plan.dir.clone().unwrap_or_else(|| input.parent().map(Path::to_path_buf).unwrap_or_default())
+}
+
+/// Applies a single operation and writes the result in the format of its input.
+fn single(config: &AppConfig, operation: Operation, output: OutputPlan) -> ConversionSettings {
+    let pipeline = Pipeline::new().push(operation);
+    ConversionSettings { pipeline, encoding: None, alpha: config.alpha, output }
+}
+
+async fn process(inputs: Vec<PathBuf>, settings: ConversionSettings, limits: Limits) -> BatchSummary {
+    let output_dir = settings.output.dir.clone().unwrap_or_default();
+    process_with(inputs, output_dir, limits, move |job| settings.convert(job)).await
+}
+
+/// Like [`process`], for tasks that write their outputs themselves.
+async fn process_with<F>(inputs: Vec<PathBuf>, output_dir: PathBuf, limits: Limits, task: F) -> BatchSummary
+    where F: Fn(&Job) -> ImageResult<Converted> + Send + Sync + 'static
+{
+    let mut summary = BatchSummary::default();
+    if let Err(e) = std::fs::create_dir_all(&output_dir) {
+        eprintln!("error: cannot create {}: {e}", output_dir.display());
+        summary.failed = inputs.len();
+        return summary;
+    }
+
+    let jobs = inputs
+        .iter()
+        .enumerate()
+        .map(|(index, input)| Job { index, id: index, input: input.clone() })
+        .collect();
+    worker::run(jobs, limits, task, |progress| {
+        match progress {
+            Progress::Started(_) => {}
+            Progress::Finished(index, Ok(converted)) => {
+                info!("Batch: {:?} -> {:?}", inputs[index], converted.output);
+                let details = converted.details.map(|d| format!(" ({d})")).unwrap_or_default();
+                println!("ok     {} -> {}{details}", inputs[index].display(), converted.output.display());
+                if let Some(warning) = converted.warning {
+                    eprintln!("warning {}: {warning}", inputs[index].display());
+                }
+                summary.succeeded += 1;
+            }
+            Progress::Finished(index, Err(e)) => {
+                error!("Batch: {:?} failed: {}", inputs[index], e);
+                eprintln!("failed {}: {e}", inputs[index].display());
+                summary.failed += 1;
+            }
+        }
+        true
+    }).await;
+
+    println!("{} succeeded, {} failed", summary.succeeded, summary.failed);
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ components::image_processing::metadata::MetadataPolicy, output::Collision };
+    use crate::components::image_processing::{
+        avif::ChromaSubsampling,
+        color_space::ToneMap,
+        formats::{ IcoSizes, PnmKind, TiffCompression },
+        optimize::Backup,
+        quantize::Dither,
+    };
+    use tempfile::tempdir;
+
+    fn convert(
+        inputs: Vec<PathBuf>,
+        output_dir: &Path,
+        template: Option<&str>,
+        on_collision: Option<Collision>
+    ) -> Command {
+        Command::Convert {
+            inputs,
+            format: ImageFormat::Jpeg,
+            quality: 80,
+            lossless: false,
+            speed: 4,
+            avif_rgb: false,
+            avif_chroma: ChromaSubsampling::Yuv444,
+            target_size: None,
+            metadata: MetadataPolicy::KeepAll,
+            no_auto_orient: false,
+            tone_map: ToneMap::Reinhard,
+            exposure: 0.0,
+            alpha: None,
+            tiff_compression: TiffCompression::Lzw,
+            ico_sizes: IcoSizes::default(),
+            pnm: PnmKind::Auto,
+            pnm_ascii: false,
+            no_tga_rle: false,
+            fast_png: false,
+            gif: gif_args(),
+            output: OutputArgs {
+                output_dir: Some(output_dir.to_path_buf()),
+                template: template.map(str::to_string),
+                on_collision,
+                mirror: false,
+            },
+        }
+    }
+
+    fn gif_args() -> GifArgs {
+        GifArgs { gif_colors: 256, gif_speed: 10, gif_dither: Dither::FloydSteinberg, gif_alpha_threshold: 128 }
+    }
+
+    #[test]
+    fn test_quality_out_of_range_is_rejected() {
+        use clap::Parser;
+        let commands: [&[&str]; 5] = [
+            &["convert", "a.png", "-f", "webp"],
+            &["optimize", "a.png"],
+            &["output-set", "a.png", "-w", "320", "-f", "webp"],
+            &["frames", "a.gif"],
+            &["animate", "a.png", "b.png", "-o", "a.gif"],
+        ];
+        for command in commands {
+            let parse = |quality: &str| {
+                let mut args = vec!["swoosh"];
+                args.extend(command);
+                args.extend(["-q", quality]);
+                crate::cli::Cli::try_parse_from(args)
+            };
+            assert!(parse("100").is_ok(), "{} rejected quality 100", command[0]);
+            assert!(parse("101").is_err(), "{} accepted quality 101", command[0]);
+        }
+    }
+
+    #[test]
+    fn test_output_dir_defaults_to_config() {
+        let mut defaults = OutputPlan::default();
+        defaults.dir = Some(PathBuf::from("out"));
+        let args = |output_dir| OutputArgs { output_dir, template: None, on_collision: None, mirror: false };
+        assert_eq!(plan(args(None), &defaults, &[]).dir, Some(PathBuf::from("out")));
+        assert_eq!(plan(args(Some(PathBuf::from("cli"))), &defaults, &[]).dir, Some(PathBuf::from("cli")));
+        assert_eq!(plan(args(None), &OutputPlan::default(), &[]).dir, None);
+    }
+
+    #[tokio::test]
+    async fn test_convert_reports_failures() {
+        let temp_dir = tempdir().unwrap();
+        let inputs = vec![PathBuf::from("tests/images/test.png"), PathBuf::from("missing.png")];
+        let command = convert(inputs, temp_dir.path(), None, None);
+        let summary = run(command, &AppConfig::default(), Limits::new(2, 0)).await;
+        assert_eq!(summary, BatchSummary { succeeded: 1, failed: 1 });
+        assert!(temp_dir.path().join("test.jpg").exists());
+    }
+
+    #[tokio::test]
+    async fn test_convert_follows_output_plan() {
+        let temp_dir = tempdir().unwrap();
+        let inputs = vec![PathBuf::from("tests/images/test.png")];
+        let template = Some("{index}-{stem}-{width}x{height}.{ext}");
+        for _ in 0..2 {
+            let command = convert(inputs.clone(), temp_dir.path(), template, Some(Collision::Skip));
+            let summary = run(command, &AppConfig::default(), Limits::new(1, 0)).await;
+            assert_eq!(summary, BatchSummary { succeeded: 1, failed: 0 });
+        }
+        let command = convert(inputs, temp_dir.path(), template, None);
+        run(command, &AppConfig::default(), Limits::new(1, 0)).await;
+
+        let mut names: Vec<_> = std::fs::read_dir(temp_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["1-test-529x661-1.jpg", "1-test-529x661.jpg"]);
+    }
+
+    #[tokio::test]
+    async fn test_animate_and_extract_frames() {
+        let temp_dir = tempdir().unwrap();
+        let animation = temp_dir.path().join("clip.gif");
+        let inputs = vec![PathBuf::from("tests/images/test.png"), PathBuf::from("tests/images/test.jpg")];
+        let command = Command::Animate {
+            inputs,
+            output: animation.clone(),
+            delay: 50,
+            quality: 10,
+            lossless: false,
+            gif: gif_args(),
+        };
+        let summary = run(command, &AppConfig::default(), Limits::new(1, 0)).await;
+        assert_eq!(summary, BatchSummary { succeeded: 1, failed: 0 });
+
+        let frames_dir = temp_dir.path().join("frames");
+        let command = Command::Frames {
+            inputs: vec![animation],
+            format: ImageFormat::Png,
+            quality: 90,
+            fast_png: true,
+            gif: gif_args(),
+            output: OutputArgs { output_dir: Some(frames_dir.clone()), template: None, on_collision: None, mirror: false },
+        };
+        let summary = run(command, &AppConfig::default(), Limits::new(1, 0)).await;
+        assert_eq!(summary, BatchSummary { succeeded: 1, failed: 0 });
+        for name in ["clip-1.png", "clip-2.png"] {
+            assert_eq!(image::image_dimensions(frames_dir.join(name)).unwrap(), (529, 661));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_posterize_writes_palette_png() {
+        let temp_dir = tempdir().unwrap();
+        let command = Command::Posterize {
+            inputs: vec![PathBuf::from("tests/images/test.png")],
+            colors: 16,
+            dither: Dither::Ordered,
+            speed: 10,
+            alpha_threshold: 128,
+            format: None,
+            output: OutputArgs { output_dir: Some(temp_dir.path().to_path_buf()), template: None, on_collision: None, mirror: false },
+        };
+        let summary = run(command, &AppConfig::default(), Limits::new(1, 0)).await;
+        assert_eq!(summary, BatchSummary { succeeded: 1, failed: 0 });
+        let file = std::fs::File::open(temp_dir.path().join("test.png")).unwrap();
+        let reader = png::Decoder::new(std::io::BufReader::new(file)).read_info().unwrap();
+        assert_eq!(reader.info().color_type, png::ColorType::Indexed);
+    }
+
+    #[tokio::test]
+    async fn test_posterize_jpeg_to_png8() {
+        let temp_dir = tempdir().unwrap();
+        let command = Command::Posterize {
+            inputs: vec![PathBuf::from("tests/images/test.jpg")],
+            colors: 8,
+            dither: Dither::None,
+            speed: 10,
+            alpha_threshold: 128,
+            format: Some(ImageFormat::Png),
+            output: OutputArgs { output_dir: Some(temp_dir.path().to_path_buf()), template: None, on_collision: None, mirror: false },
+        };
+        let summary = run(command, &AppConfig::default(), Limits::new(1, 0)).await;
+        assert_eq!(summary, BatchSummary { succeeded: 1, failed: 0 });
+        let file = std::fs::File::open(temp_dir.path().join("test.png")).unwrap();
+        let reader = png::Decoder::new(std::io::BufReader::new(file)).read_info().unwrap();
+        assert_eq!(reader.info().color_type, png::ColorType::Indexed);
+    }
+
+    #[tokio::test]
+    async fn test_optimize_in_place() {
+        let temp_dir = tempdir().unwrap();
+        let input = temp_dir.path().join("test.jpg");
+        std::fs::copy("tests/images/test.jpg", &input).unwrap();
+        let original_size = std::fs::metadata(&input).unwrap().len();
+        let command = Command::Optimize {
+            inputs: vec![input.clone()],
+            quality: 40,
+            min_savings: Some(1.0),
+            backup: Some(Backup::Bak),
+            trash_dir: None,
+            metadata: MetadataPolicy::KeepAll,
+        };
+        let summary = run(command, &AppConfig::default(), Limits::new(1, 0)).await;
+        assert_eq!(summary, BatchSummary { succeeded: 1, failed: 0 });
+        assert!(std::fs::metadata(&input).unwrap().len() < original_size);
+        assert_eq!(std::fs::metadata(temp_dir.path().join("test.jpg.bak")).unwrap().len(), original_size);
+    }
+}
@@ -1,8 +1,24 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{ Args, Parser, Subcommand };
+use image::{ ImageFormat, Rgba };
 
-use crate::config::{ get_config_dir, get_data_dir };
+use crate::{
+    components::image_processing::{
+        animation,
+        avif::ChromaSubsampling,
+        color_space::ToneMap,
+        formats::{ IcoSizes, PnmKind, TiffCompression },
+        metadata::MetadataPolicy,
+        optimize::Backup,
+        quantize::Dither,
+        transform::{
+            parse_color, AlphaPolicy, AspectRatio, Gravity, Interpolation, KernelPreset, ResizeFilter, ResizeMode,
+        },
+    },
+    config::{ get_config_dir, get_data_dir },
+    output::Collision,
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version = version(), about)]
@@ -17,6 +33,396 @@ pub struct Cli {
 
     #[arg(short, long, value_name = "FILE", num_args = 1..)]
     pub images: Option<Vec<PathBuf>>,
+
+    /// Number of images converted in parallel, defaults to the `jobs` config key or one per core
+    #[arg(short, long, value_name = "INT", global = true)]
+    pub jobs: Option<usize>,
+
+    /// Run a batch operation without starting the TUI
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Convert images to another format
+    Convert {
+        #[arg(value_name = "FILE", num_args = 1.., required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Output format, e.g. png, jpeg, webp, avif, gif, exr, tiff, bmp, ico, qoi, pnm, tga, ff
+        #[arg(short, long, value_name = "FORMAT", value_parser = parse_format)]
+        format: ImageFormat,
+
+        /// Encoder quality (1-100)
+        #[arg(short, long, value_name = "INT", default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+        quality: u8,
+
+        /// Write WebP losslessly instead of at `--quality`
+        #[arg(long)]
+        lossless: bool,
+
+        /// AVIF encoder speed, 1 (slowest, smallest) to 10 (fastest)
+        #[arg(long, value_name = "INT", default_value_t = 4, value_parser = clap::value_parser!(u8).range(1..=10))]
+        speed: u8,
+
+        /// Encode AVIF as RGB planes instead of YCbCr
+        #[arg(long, conflicts_with = "avif_chroma")]
+        avif_rgb: bool,
+
+        /// AVIF chroma subsampling, 420 halves the color resolution for smaller photos
+        #[arg(long, value_name = "SUBSAMPLING", value_enum, default_value_t = ChromaSubsampling::Yuv444)]
+        avif_chroma: ChromaSubsampling,
+
+        /// Pick the highest quality that fits this size, e.g. `200KB` (JPEG, lossy WebP, AVIF)
+        #[arg(long, value_name = "SIZE")]
+        target_size: Option<bytesize::ByteSize>,
+
+        /// Which EXIF, ICC and XMP metadata to carry over from the source, AVIF keeps EXIF only
+        #[arg(long, value_name = "POLICY", value_enum, default_value_t = MetadataPolicy::KeepAll)]
+        metadata: MetadataPolicy,
+
+        /// Keep the stored pixel layout instead of rotating from the EXIF orientation
+        #[arg(long)]
+        no_auto_orient: bool,
+
+        /// How HDR sources such as OpenEXR are fitted into formats with integer samples
+        #[arg(long, value_name = "OPERATOR", value_enum, default_value_t = ToneMap::Reinhard)]
+        tone_map: ToneMap,
+
+        /// Exposure adjustment for HDR sources in stops, e.g. -1 for half the light
+        #[arg(long, value_name = "FLOAT", default_value_t = 0.0, allow_negative_numbers = true)]
+        exposure: f32,
+
+        /// Transparency for formats without alpha such as JPEG: an opaque background color like
+        /// `#ffffff`, `checkerboard` or `error`, defaults to the `alpha` config key or white
+        #[arg(long, value_name = "POLICY")]
+        alpha: Option<AlphaPolicy>,
+
+        /// TIFF compression, all of them lossless
+        #[arg(long, value_name = "METHOD", value_enum, default_value_t = TiffCompression::Lzw)]
+        tiff_compression: TiffCompression,
+
+        /// Comma separated ICO sizes out of 16, 24, 32, 48, 64, 128 and 256, or `all`
+        #[arg(long, value_name = "INT,...", default_value = "16,32,48")]
+        ico_sizes: IcoSizes,
+
+        /// PNM variant, `auto` picks PGM, PPM or PAM from the image
+        #[arg(long, value_name = "KIND", value_enum, default_value_t = PnmKind::Auto)]
+        pnm: PnmKind,
+
+        /// Write PBM, PGM and PPM samples as text
+        #[arg(long)]
+        pnm_ascii: bool,
+
+        /// Write TGA without run length encoding
+        #[arg(long)]
+        no_tga_rle: bool,
+
+        /// Write PNG in one pass instead of trying every row filter for the smallest file
+        #[arg(long)]
+        fast_png: bool,
+
+        #[command(flatten)]
+        gif: GifArgs,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Recompress images in place in their own format, replacing only files that get smaller
+    Optimize {
+        #[arg(value_name = "FILE", num_args = 1.., required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Encoder quality (1-100)
+        #[arg(short, long, value_name = "INT", default_value_t = 80, value_parser = clap::value_parser!(u8).range(1..=100))]
+        quality: u8,
+
+        /// Smallest saving worth replacing a file for, in percent, defaults to the
+        /// `optimize.min_savings` config key or 1
+        #[arg(long, value_name = "PERCENT")]
+        min_savings: Option<f32>,
+
+        /// Copy kept of each replaced original, defaults to the `optimize.backup` config key
+        #[arg(long, value_name = "BACKUP", value_enum)]
+        backup: Option<Backup>,
+
+        /// Directory for `--backup trash`, defaults to the `optimize.trash_dir` config key or
+        /// `trash` in the data directory
+        #[arg(long, value_name = "DIR")]
+        trash_dir: Option<PathBuf>,
+
+        /// Which EXIF, ICC and XMP metadata to keep
+        #[arg(long, value_name = "POLICY", value_enum, default_value_t = MetadataPolicy::KeepAll)]
+        metadata: MetadataPolicy,
+    },
+    /// Write several widths of each image in one or more formats, e.g. for a `srcset`, named
+    /// `{stem}-{width}w.{ext}` unless `--template` is given
+    OutputSet {
+        #[arg(value_name = "FILE", num_args = 1.., required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Comma separated widths, larger than the source are capped at the source width
+        #[arg(short, long, value_name = "INT,...", value_delimiter = ',', required = true)]
+        widths: Vec<u32>,
+
+        /// Comma separated output formats, e.g. webp,avif
+        #[arg(short, long, value_name = "FORMAT,...", value_delimiter = ',', value_parser = parse_format, required = true)]
+        formats: Vec<ImageFormat>,
+
+        /// Encoder quality (1-100)
+        #[arg(short, long, value_name = "INT", default_value_t = 80, value_parser = clap::value_parser!(u8).range(1..=100))]
+        quality: u8,
+
+        /// Resampling filter, defaults to the `resize_filter` config key or lanczos3
+        #[arg(long, value_name = "FILTER", value_enum)]
+        filter: Option<ResizeFilter>,
+
+        /// Which EXIF, ICC and XMP metadata to carry over from the source, AVIF keeps EXIF only
+        #[arg(long, value_name = "POLICY", value_enum, default_value_t = MetadataPolicy::KeepAll)]
+        metadata: MetadataPolicy,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Write every frame of animated GIF, APNG or WebP images as its own image, named
+    /// `{stem}-{index}.{ext}` unless `--template` is given
+    Frames {
+        #[arg(value_name = "FILE", num_args = 1.., required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Format of the frames
+        #[arg(short, long, value_name = "FORMAT", default_value = "png", value_parser = parse_format)]
+        format: ImageFormat,
+
+        /// Encoder quality (1-100)
+        #[arg(short, long, value_name = "INT", default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+        quality: u8,
+
+        /// Write PNG frames in one pass instead of trying every row filter for the smallest file
+        #[arg(long)]
+        fast_png: bool,
+
+        #[command(flatten)]
+        gif: GifArgs,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Build an animated GIF, APNG or WebP with one frame per image, in the order given
+    Animate {
+        #[arg(value_name = "FILE", num_args = 2.., required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Animation file, its extension picks the format
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+
+        /// How long each frame is shown, in milliseconds
+        #[arg(short, long, value_name = "MS", default_value_t = animation::DEFAULT_DELAY_MS)]
+        delay: u64,
+
+        /// Encoder quality (1-100), GIF encodes faster with less
+        #[arg(short, long, value_name = "INT", default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+        quality: u8,
+
+        /// Write WebP losslessly instead of at `--quality`
+        #[arg(long)]
+        lossless: bool,
+
+        #[command(flatten)]
+        gif: GifArgs,
+    },
+    /// Reduce images to a few colors, written in their own format, e.g. 8-bit PNG
+    Posterize {
+        #[arg(value_name = "FILE", num_args = 1.., required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Number of colors, 2 to 256
+        #[arg(short, long, value_name = "INT", value_parser = clap::value_parser!(u16).range(2..=256))]
+        colors: u16,
+
+        /// How colors missing from the palette are approximated
+        #[arg(long, value_name = "METHOD", value_enum, default_value_t = Dither::FloydSteinberg)]
+        dither: Dither,
+
+        /// Quantizer speed, 1 (slowest, best palette) to 30 (fastest)
+        #[arg(long, value_name = "INT", default_value_t = 10, value_parser = clap::value_parser!(u8).range(1..=30))]
+        speed: u8,
+
+        /// Alpha below which pixels become transparent, 0 keeps every pixel opaque
+        #[arg(long, value_name = "INT", default_value_t = 128)]
+        alpha_threshold: u8,
+
+        /// Output format, e.g. `png` for an 8-bit palette PNG, defaults to the format of each input
+        #[arg(short, long, value_name = "FORMAT", value_parser = parse_format)]
+        format: Option<ImageFormat>,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Resize images to a box, a percentage or a pixel count
+    Resize {
+        #[arg(value_name = "FILE", num_args = 1.., required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Target width, 0 to derive it from the height
+        #[arg(short = 'W', long, value_name = "INT", default_value_t = 0)]
+        width: u32,
+
+        /// Target height, 0 to derive it from the width
+        #[arg(short = 'H', long, value_name = "INT", default_value_t = 0)]
+        height: u32,
+
+        /// Scale both sides by a percentage instead, e.g. 50
+        #[arg(long, value_name = "FLOAT", conflicts_with_all = ["width", "height", "megapixels"])]
+        percent: Option<f32>,
+
+        /// Scale to about this many million pixels instead, e.g. 2.5
+        #[arg(long, value_name = "FLOAT", conflicts_with_all = ["width", "height"])]
+        megapixels: Option<f32>,
+
+        /// How the image is fitted into the width x height box
+        #[arg(short, long, value_name = "MODE", value_enum, default_value_t = ResizeMode::Fit)]
+        mode: ResizeMode,
+
+        /// Resampling filter, defaults to the `resize_filter` config key or lanczos3
+        #[arg(long, value_name = "FILTER", value_enum)]
+        filter: Option<ResizeFilter>,
+
+        /// Shorthand for `--mode fill`
+        #[arg(long, conflicts_with = "mode")]
+        exact: bool,
+
+        /// Padding of `--mode contain`, `transparent` or a hex color like `#ffffff`
+        #[arg(long, value_name = "COLOR", default_value = "transparent", value_parser = parse_color)]
+        background: Rgba<u8>,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Rotate images by any angle, e.g. 90 or -1.7
+    Rotate {
+        #[arg(value_name = "FILE", num_args = 1.., required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Rotation angle in degrees (clockwise)
+        #[arg(short, long, value_name = "FLOAT", allow_negative_numbers = true)]
+        degrees: f32,
+
+        /// Resampling for angles that are not a multiple of 90
+        #[arg(long, value_name = "METHOD", value_enum, default_value_t = Interpolation::Bilinear)]
+        interpolation: Interpolation,
+
+        /// Keep the original canvas size and clip the corners instead of growing to fit
+        #[arg(long)]
+        keep_size: bool,
+
+        /// Fill for uncovered areas, `transparent` or a hex color like `#ffffff`
+        #[arg(long, value_name = "COLOR", default_value = "transparent", value_parser = parse_color)]
+        background: Rgba<u8>,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Crop images to an aspect ratio, e.g. 16:9, keeping the part picked by `--gravity`
+    Crop {
+        #[arg(value_name = "FILE", num_args = 1.., required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Target aspect ratio as `width:height`
+        #[arg(short, long, value_name = "W:H")]
+        aspect: AspectRatio,
+
+        /// Part of the image to keep, `attention` follows the most detailed region
+        #[arg(short, long, value_name = "GRAVITY", value_enum, default_value_t = Gravity::Center)]
+        gravity: Gravity,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Crop away uniform borders around the content
+    Trim {
+        #[arg(value_name = "FILE", num_args = 1.., required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Largest color difference (CIE76) still counted as border
+        #[arg(short, long, value_name = "FLOAT", default_value_t = 10.0)]
+        tolerance: f32,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Straighten scans and photos of documents
+    Deskew {
+        #[arg(value_name = "FILE", num_args = 1.., required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Largest tilt searched for, in degrees either way
+        #[arg(long, value_name = "FLOAT", default_value_t = 15.0)]
+        max_angle: f32,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Apply a 3x3 convolution kernel, e.g. to sharpen or find edges
+    Filter {
+        #[arg(value_name = "FILE", num_args = 1.., required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Kernel to apply, defaults to the `kernel` config key or sharpen
+        #[arg(short, long, value_name = "KERNEL", value_enum)]
+        kernel: Option<KernelPreset>,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+}
+
+/// Where a batch command writes its results.
+#[derive(Args, Debug)]
+pub struct OutputArgs {
+    /// Directory the results are written to, defaults to the `output.dir` config key or next to
+    /// each input
+    #[arg(short, long, value_name = "DIR")]
+    pub output_dir: Option<PathBuf>,
+
+    /// File name with `{stem}`, `{ext}`, `{width}`, `{height}`, `{index}` and `{date}` tokens,
+    /// defaults to the `output.template` config key or `{stem}.{ext}`
+    #[arg(long, value_name = "TEMPLATE")]
+    pub template: Option<String>,
+
+    /// What to do when an output exists, defaults to the `output.collision` config key
+    #[arg(long, value_name = "POLICY", value_enum)]
+    pub on_collision: Option<Collision>,
+
+    /// Recreate the directories of the inputs below the output directory
+    #[arg(long)]
+    pub mirror: bool,
+}
+
+/// Palette settings of GIF outputs.
+#[derive(Args, Debug)]
+pub struct GifArgs {
+    /// GIF palette size, one entry goes to transparency when the image has any
+    #[arg(long, value_name = "INT", default_value_t = 256, value_parser = clap::value_parser!(u16).range(2..=256))]
+    pub gif_colors: u16,
+
+    /// GIF quantizer speed, 1 (slowest, best palette) to 30 (fastest)
+    #[arg(long, value_name = "INT", default_value_t = 10, value_parser = clap::value_parser!(u8).range(1..=30))]
+    pub gif_speed: u8,
+
+    /// How GIF colors missing from the palette are approximated
+    #[arg(long, value_name = "METHOD", value_enum, default_value_t = Dither::FloydSteinberg)]
+    pub gif_dither: Dither,
+
+    /// Alpha below which GIF pixels become transparent, 0 keeps every pixel opaque
+    #[arg(long, value_name = "INT", default_value_t = 128)]
+    pub gif_alpha_threshold: u8,
+}
+
+fn parse_format(s: &str) -> Result<ImageFormat, String> {
+    ImageFormat::from_extension(s).ok_or_else(|| format!("Unknown image format `{s}`"))
 }
 
 const VERSION_MESSAGE: &str = concat!(
@@ -6,8 +6,10 @@ use tokio::sync::mpsc::UnboundedSender;
 use crate::{ action::Action, config::Config, tui::Event };
 
 pub mod fps;
+pub mod image_processing;
 pub mod options;
 pub mod list;
+pub mod settings;
 pub mod value;
 
 /// `Component` is a trait that represents a visual and interactive element of the user interface.
@@ -1,3 +1,5 @@
+#![allow(dead_code)] // Remove this once you start using the code
+
 use std::time::Instant;
 
 use color_eyre::Result;
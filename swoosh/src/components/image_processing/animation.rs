@@ -0,0 +1,434 @@
+// src/animation.rs
+use image::{
+    codecs::{ gif::GifDecoder, png::PngDecoder, webp::WebPDecoder },
+    error,
+    AnimationDecoder,
+    Delay,
+    DynamicImage,
+    Frame,
+    ImageError,
+    ImageFormat,
+    ImageReader,
+    ImageResult,
+};
+use img_parts::webp::{ WebP, CHUNK_ANMF };
+use libwebp_sys::{
+    WebPAnimEncoder,
+    WebPAnimEncoderAdd,
+    WebPAnimEncoderAssemble,
+    WebPAnimEncoderDelete,
+    WebPAnimEncoderGetError,
+    WebPAnimEncoderNewInternal,
+    WebPAnimEncoderOptions,
+    WebPAnimEncoderOptionsInitInternal,
+    WebPConfig,
+    WebPData,
+    WebPDataClear,
+    WebPGetMuxABIVersion,
+    WebPPicture,
+    WebPPictureFree,
+    WebPPictureImportRGBA,
+};
+use std::{
+    ffi::{ c_int, CStr },
+    fs::{ self, File },
+    io::BufReader,
+    mem::MaybeUninit,
+    path::{ Path, PathBuf },
+    ptr,
+    slice,
+    time::Duration,
+};
+use tracing::{ debug, info, instrument };
+
+use super::{
+    compress::{ write_image, EncodeOptions },
+    metadata::Metadata,
+    pipeline::Pipeline,
+    quantize,
+    transform::{ self, ResizeMode, ResizeOptions, ResizeSize },
+};
+use crate::output::{ ensure_not_source, write_atomic, OutputPlan, Planned, Tokens };
+
+/// File names of extracted frames, numbered from 1, e.g. `clip-1.png`.
+pub const FRAMES_TEMPLATE: &str = "{stem}-{index}.{ext}";
+
+/// File name of an animation built from several images, after the first one.
+pub const ANIMATION_TEMPLATE: &str = "{stem}-animated.{ext}";
+
+/// Frame delay of [`build_animation`] unless set.
+pub const DEFAULT_DELAY_MS: u64 = 100;
+
+/// Whether `format` can be written with several frames: GIF, APNG or animated WebP.
+pub fn supports_animation(format: ImageFormat) -> bool {
+    matches!(format, ImageFormat::Gif | ImageFormat::Png | ImageFormat::WebP)
+}
+
+/// Whether `path` is a GIF, APNG or WebP with more than one frame.
+pub fn is_animated(path: &Path) -> ImageResult<bool> {
+    let reader = || -> ImageResult<BufReader<File>> { Ok(BufReader::new(File::open(path)?)) };
+    match ImageReader::open(path)?.with_guessed_format()?.format() {
+        Some(ImageFormat::Gif) => Ok(GifDecoder::new(reader()?)?.into_frames().take(2).count() > 1),
+        Some(ImageFormat::Png) => PngDecoder::new(reader()?)?.is_apng(),
+        Some(ImageFormat::WebP) => Ok(WebPDecoder::new(reader()?)?.has_animation()),
+        _ => Ok(false),
+    }
+}
+
+/// Number of frames of `path`, read from the container without decoding any pixels.
+///
+/// Still images, and files in formats without animation, count as a single frame.
+pub fn frame_count(path: &Path) -> ImageResult<usize> {
+    let reader = BufReader::new(File::open(path)?);
+    let count = match ImageReader::open(path)?.with_guessed_format()?.format() {
+        Some(ImageFormat::Gif) => {
+            let mut options = gif::DecodeOptions::new();
+            options.skip_frame_decoding(true);
+            let mut decoder = options.read_info(reader).map_err(decoding_error(ImageFormat::Gif))?;
+            let mut count = 0;
+            while decoder.read_next_frame().map_err(decoding_error(ImageFormat::Gif))?.is_some() {
+                count += 1;
+            }
+            count
+        }
+        Some(ImageFormat::Png) => {
+            let reader = png::Decoder::new(reader).read_info().map_err(decoding_error(ImageFormat::Png))?;
+            reader.info().animation_control().map_or(1, |control| control.num_frames as usize)
+        }
+        Some(ImageFormat::WebP) => {
+            let webp = WebP::from_bytes(fs::read(path)?.into()).map_err(decoding_error(ImageFormat::WebP))?;
+            webp.chunks_by_id(CHUNK_ANMF).count()
+        }
+        _ => 1,
+    };
+    Ok(count.max(1))
+}
+
+/// Decodes every frame of `path` with its delay, each covering the whole canvas.
+///
+/// Still images come back as a single frame.
+pub fn decode_frames(path: &Path) -> ImageResult<Vec<Frame>> {
+    let reader = BufReader::new(File::open(path)?);
+    let frames = match ImageReader::open(path)?.with_guessed_format()?.format() {
+        Some(ImageFormat::Gif) => GifDecoder::new(reader)?.into_frames(),
+        Some(ImageFormat::Png) if is_animated(path)? => PngDecoder::new(reader)?.apng()?.into_frames(),
+        Some(ImageFormat::WebP) => WebPDecoder::new(reader)?.into_frames(),
+        _ => {
+            return Ok(vec![Frame::new(image::open(path)?.to_rgba8())]);
+        }
+    };
+    let frames = frames.collect_frames()?;
+    if frames.is_empty() {
+        return Err(parameter_error(format!("{} has no frames", path.display())));
+    }
+    Ok(frames)
+}
+
+/// Runs `pipeline` on every frame, keeping the delays.
+///
+/// Fails when the operations leave frames of different sizes, as trim or an attention crop can.
+pub fn apply(pipeline: &Pipeline, frames: Vec<Frame>) -> ImageResult<Vec<Frame>> {
+    if pipeline.operations().is_empty() {
+        return Ok(frames);
+    }
+    let frames = frames
+        .into_iter()
+        .map(|frame| {
+            let delay = frame.delay();
+            let img = pipeline.apply(DynamicImage::ImageRgba8(frame.into_buffer()))?;
+            Ok(Frame::from_parts(img.to_rgba8(), 0, 0, delay))
+        })
+        .collect::<ImageResult<Vec<_>>>()?;
+    let size = frames.first().map(|frame| frame.buffer().dimensions());
+    if frames.iter().any(|frame| Some(frame.buffer().dimensions()) != size) {
+        return Err(parameter_error("The operations left the frames in different sizes".to_string()));
+    }
+    Ok(frames)
+}
+
+/// Encodes `frames` as an animation in `options.format`, looping forever.
+///
+/// GIF quantizes every frame with `options.gif`, WebP uses `quality` and `webp_lossless`, APNG is
+/// lossless. Metadata is not written.
+pub fn encode_animation(frames: &[Frame], options: &EncodeOptions) -> ImageResult<Vec<u8>> {
+    let Some(first) = frames.first() else {
+        return Err(parameter_error("An animation needs at least one frame".to_string()));
+    };
+    let (width, height) = first.buffer().dimensions();
+    let mut encoded = Vec::new();
+    match options.format {
+        ImageFormat::Gif => quantize::encode_gif(frames, &options.gif, &mut encoded)?,
+        ImageFormat::Png => {
+            let mut encoder = png::Encoder::new(&mut encoded, width, height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_animated(frames.len() as u32, 0).map_err(png_error)?;
+            let mut writer = encoder.write_header().map_err(png_error)?;
+            for frame in frames {
+                let delay = delay_ms(frame).min(u64::from(u16::MAX)) as u16;
+                writer.set_frame_delay(delay, 1000).map_err(png_error)?;
+                writer.write_image_data(frame.buffer().as_raw()).map_err(png_error)?;
+            }
+            writer.finish().map_err(png_error)?;
+        }
+        ImageFormat::WebP => {
+            let mut config = WebPConfig::new().map_err(|()| webp_error("Invalid WebP configuration".to_string()))?;
+            config.lossless = i32::from(options.webp_lossless);
+            config.quality = f32::from(options.quality);
+            encoded = encode_webp(frames, (width, height), &config)?;
+        }
+        format => {
+            return Err(ImageError::Unsupported(error::ImageFormatHint::Exact(format).into()));
+        }
+    }
+    Ok(encoded)
+}
+
+/// Encodes `frames` like [`encode_animation`] into the file at `output_path`, returning its size.
+pub fn write_animation(frames: &[Frame], output_path: &Path, options: &EncodeOptions) -> ImageResult<u64> {
+    let encoded = encode_animation(frames, options)?;
+    write_atomic(output_path, &encoded)?;
+    Ok(encoded.len() as u64)
+}
+
+/// Writes every frame of `input_path` as its own image where `plan` puts it, `{index}` being the
+/// frame number.
+///
+/// Frames the plan skips are left out.
+#[instrument(level = "info", skip_all, fields(input_path = %input_path.display(), format = ?options.format))]
+pub fn extract_frames(
+    input_path: &Path,
+    pipeline: &Pipeline,
+    plan: &OutputPlan,
+    options: &EncodeOptions
+) -> ImageResult<Vec<PathBuf>> {
+    if !plan.template.contains("{index}") {
+        return Err(parameter_error("The frame template needs an {index} placeholder".to_string()));
+    }
+    let frames = apply(pipeline, decode_frames(input_path)?)?;
+    let mut paths = Vec::new();
+    for (n, frame) in frames.into_iter().enumerate() {
+        let tokens = Tokens::new(input_path, options.extension(), frame.buffer().dimensions(), n + 1);
+        let path = match plan.resolve(input_path, &tokens)? {
+            Planned::Write(path) => path,
+            Planned::Skip(path) => {
+                debug!("Skipping existing {path:?}");
+                continue;
+            }
+        };
+        ensure_not_source(input_path, &path)?;
+        write_image(&DynamicImage::ImageRgba8(frame.into_buffer()), &Metadata::default(), &path, options)?;
+        paths.push(path);
+    }
+    info!(message = "Frames extracted", frames = paths.len());
+    Ok(paths)
+}
+
+/// Builds an animation from `inputs` in order, one frame each shown for `delay`.
+///
+/// Every input runs through `pipeline`, and inputs of a different size than the first are fitted
+/// into its canvas with transparent padding. Returns the size of the written file.
+#[instrument(level = "info", skip_all, fields(inputs = inputs.len(), output_path = %output_path.display()))]
+pub fn build_animation(
+    inputs: &[PathBuf],
+    pipeline: &Pipeline,
+    delay: Duration,
+    output_path: &Path,
+    options: &EncodeOptions
+) -> ImageResult<u64> {
+    let delay = Delay::from_saturating_duration(delay);
+    let mut frames: Vec<Frame> = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        ensure_not_source(input, output_path)?;
+        let (mut img, _) = pipeline.decode(input)?;
+        if let Some(first) = frames.first() {
+            let (width, height) = first.buffer().dimensions();
+            if (img.width(), img.height()) != (width, height) {
+                let fit = ResizeOptions::new(ResizeSize::Pixels { width, height }, ResizeMode::Contain);
+                img = transform::resize(&img, &fit)?;
+            }
+        }
+        frames.push(Frame::from_parts(img.to_rgba8(), 0, 0, delay));
+    }
+    let size = write_animation(&frames, output_path, options)?;
+    info!(message = "Animation built", frames = frames.len(), size);
+    Ok(size)
+}
+
+/// Encodes an animated WebP with libwebp, looping forever.
+///
+/// `webp::AnimEncoder` closes the stream at timestamp 0, which libwebp rejects before giving the
+/// last frame the average delay of the others, so the end of the last frame is passed here.
+fn encode_webp(frames: &[Frame], (width, height): (u32, u32), config: &WebPConfig) -> ImageResult<Vec<u8>> {
+    let (width, height) = (width as c_int, height as c_int);
+    // SAFETY: every pointer handed to libwebp outlives the call, the pictures are freed right
+    // after they are added and `AnimEncoder` deletes the encoder on every path.
+    unsafe {
+        let mut anim_options = MaybeUninit::<WebPAnimEncoderOptions>::uninit();
+        if WebPAnimEncoderOptionsInitInternal(anim_options.as_mut_ptr(), WebPGetMuxABIVersion()) == 0 {
+            return Err(webp_error("Incompatible libwebp version".to_string()));
+        }
+        let anim_options = anim_options.assume_init();
+        let encoder = WebPAnimEncoderNewInternal(width, height, &anim_options, WebPGetMuxABIVersion());
+        if encoder.is_null() {
+            return Err(webp_error("Cannot create the WebP animation encoder".to_string()));
+        }
+        let encoder = AnimEncoder(encoder);
+
+        let mut timestamp: c_int = 0;
+        for frame in frames {
+            let mut picture = WebPPicture::new().map_err(|()| webp_error("Invalid WebP picture".to_string()))?;
+            picture.use_argb = 1;
+            picture.width = width;
+            picture.height = height;
+            let added = WebPPictureImportRGBA(&mut picture, frame.buffer().as_ptr(), width * 4) != 0 &&
+                WebPAnimEncoderAdd(encoder.0, &mut picture, timestamp, config) != 0;
+            WebPPictureFree(&mut picture);
+            if !added {
+                return Err(webp_error(encoder.error()));
+            }
+            timestamp = timestamp.saturating_add(c_int::try_from(delay_ms(frame)).unwrap_or(c_int::MAX));
+        }
+        if WebPAnimEncoderAdd(encoder.0, ptr::null_mut(), timestamp, ptr::null()) == 0 {
+            return Err(webp_error(encoder.error()));
+        }
+
+        let mut data = WebPData::default();
+        if WebPAnimEncoderAssemble(encoder.0, &mut data) == 0 {
+            return Err(webp_error(encoder.error()));
+        }
+        let encoded = slice::from_raw_parts(data.bytes, data.size).to_vec();
+        WebPDataClear(&mut data);
+        Ok(encoded)
+    }
+}
+
+/// Owns a libwebp animation encoder.
+struct AnimEncoder(*mut WebPAnimEncoder);
+
+impl AnimEncoder {
+    /// The last error of the encoder.
+    unsafe fn error(&self) -> String {
+        let message = WebPAnimEncoderGetError(self.0);
+        if message.is_null() {
+            return "Unknown WebP encoding error".to_string();
+        }
+        CStr::from_ptr(message).to_string_lossy().into_owned()
+    }
+}
+
+impl Drop for AnimEncoder {
+    fn drop(&mut self) {
+        // SAFETY: the pointer came from `WebPAnimEncoderNewInternal` and is deleted once
+        unsafe { WebPAnimEncoderDelete(self.0) }
+    }
+}
+
+fn delay_ms(frame: &Frame) -> u64 {
+    Duration::from(frame.delay()).as_millis() as u64
+}
+
+fn parameter_error(message: String) -> ImageError {
+    ImageError::Parameter(error::ParameterError::from_kind(error::ParameterErrorKind::Generic(message)))
+}
+
+fn decoding_error<E>(format: ImageFormat) -> impl FnOnce(E) -> ImageError
+    where E: Into<Box<dyn std::error::Error + Send + Sync>>
+{
+    move |e| ImageError::Decoding(error::DecodingError::new(format.into(), e))
+}
+
+fn png_error(e: png::EncodingError) -> ImageError {
+    ImageError::Encoding(error::EncodingError::new(ImageFormat::Png.into(), e))
+}
+
+fn webp_error(message: String) -> ImageError {
+    ImageError::Encoding(error::EncodingError::new(ImageFormat::WebP.into(), message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::image_processing::pipeline::Operation;
+    use image::{ Rgba, RgbaImage };
+    use tempfile::tempdir;
+    use tracing_test::traced_test;
+
+    /// Three 8x6 frames in red, green and blue, 40, 80 and 120 ms long.
+    fn frames() -> Vec<Frame> {
+        [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]]
+            .into_iter()
+            .zip(1..)
+            .map(|(color, n)| {
+                let delay = Delay::from_numer_denom_ms(40 * n, 1);
+                Frame::from_parts(RgbaImage::from_pixel(8, 6, Rgba(color)), 0, 0, delay)
+            })
+            .collect()
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_animations_round_trip() {
+        let temp_dir = tempdir().unwrap();
+        for (format, extension) in [(ImageFormat::Gif, "gif"), (ImageFormat::Png, "png"), (ImageFormat::WebP, "webp")] {
+            let path = temp_dir.path().join(format!("clip.{extension}"));
+            let options = EncodeOptions { webp_lossless: true, ..EncodeOptions::new(format, 90) };
+            write_animation(&frames(), &path, &options).unwrap();
+
+            assert!(is_animated(&path).unwrap(), "{format:?}");
+            assert_eq!(frame_count(&path).unwrap(), 3, "{format:?}");
+            let decoded = decode_frames(&path).unwrap();
+            assert_eq!(decoded.len(), 3, "{format:?}");
+            // The WebP decoder blends frames onto the canvas, which can be one off
+            let pixel = decoded[1].buffer().get_pixel(4, 3);
+            assert!(pixel.0.iter().zip([0, 255, 0, 255]).all(|(a, b)| a.abs_diff(b) <= 1), "{format:?} {pixel:?}");
+            let delays: Vec<u64> = decoded.iter().map(delay_ms).collect();
+            assert_eq!(delays, [40, 80, 120], "{format:?}");
+        }
+        assert!(!is_animated(Path::new("tests/images/test.png")).unwrap());
+        assert_eq!(frame_count(Path::new("tests/images/test.jpg")).unwrap(), 1);
+        temp_dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_apply_runs_on_every_frame() {
+        let pipeline = Pipeline::new().push(Operation::Crop { x: 0, y: 0, width: 4, height: 2 });
+        let cropped = apply(&pipeline, frames()).unwrap();
+        assert!(cropped.iter().all(|frame| frame.buffer().dimensions() == (4, 2)));
+        assert_eq!(delay_ms(&cropped[2]), 120);
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_extract_frames() {
+        let temp_dir = tempdir().unwrap();
+        let input = temp_dir.path().join("clip.gif");
+        write_animation(&frames(), &input, &EncodeOptions::new(ImageFormat::Gif, 90)).unwrap();
+        let plan = OutputPlan::new(FRAMES_TEMPLATE);
+        let paths = extract_frames(&input, &Pipeline::new(), &plan, &EncodeOptions::new(ImageFormat::Png, 90)).unwrap();
+        let names: Vec<_> = paths.iter().map(|path| path.file_name().unwrap().to_string_lossy()).collect();
+        assert_eq!(names, ["clip-1.png", "clip-2.png", "clip-3.png"]);
+        assert_eq!(image::open(&paths[2]).unwrap().to_rgba8().get_pixel(0, 0), &Rgba([0, 0, 255, 255]));
+
+        let unnumbered = OutputPlan::new("{stem}.{ext}");
+        assert!(extract_frames(&input, &Pipeline::new(), &unnumbered, &EncodeOptions::new(ImageFormat::Png, 90)).is_err());
+        temp_dir.close().unwrap();
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_build_animation_fits_inputs() {
+        let temp_dir = tempdir().unwrap();
+        let inputs = vec![PathBuf::from("tests/images/test.png"), PathBuf::from("tests/images/test.jpg")];
+        let output = temp_dir.path().join("slides.gif");
+        let options = EncodeOptions::new(ImageFormat::Gif, 90);
+        build_animation(&inputs, &Pipeline::new(), Duration::from_millis(500), &output, &options).unwrap();
+
+        let decoded = decode_frames(&output).unwrap();
+        assert_eq!(decoded.len(), 2);
+        let size = image::image_dimensions("tests/images/test.png").unwrap();
+        assert!(decoded.iter().all(|frame| frame.buffer().dimensions() == size));
+        assert_eq!(delay_ms(&decoded[1]), 500);
+        temp_dir.close().unwrap();
+    }
+}
@@ -0,0 +1,305 @@
+// src/avif.rs
+use avif_serialize::{ constants, Aviffy };
+use image::{ codecs::avif::ColorSpace, error, ImageError, ImageFormat, ImageResult, RgbaImage };
+use rav1e::prelude::*;
+use std::io::Write;
+
+/// Resolution of the AVIF chroma planes relative to luma.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ChromaSubsampling {
+    /// Full resolution chroma, keeps thin colored lines and text sharp.
+    #[default]
+    #[value(name = "444")]
+    Yuv444,
+    /// Half the horizontal chroma resolution.
+    #[value(name = "422")]
+    Yuv422,
+    /// Half the chroma resolution in both directions, the smallest files for photos.
+    #[value(name = "420")]
+    Yuv420,
+}
+
+impl ChromaSubsampling {
+    pub fn label(self) -> &'static str {
+        match self {
+            ChromaSubsampling::Yuv444 => "4:4:4",
+            ChromaSubsampling::Yuv422 => "4:2:2",
+            ChromaSubsampling::Yuv420 => "4:2:0",
+        }
+    }
+
+    /// Whether chroma is halved horizontally and vertically.
+    fn subsampled(self) -> (bool, bool) {
+        match self {
+            ChromaSubsampling::Yuv444 => (false, false),
+            ChromaSubsampling::Yuv422 => (true, false),
+            ChromaSubsampling::Yuv420 => (true, true),
+        }
+    }
+
+    fn sampling(self) -> ChromaSampling {
+        match self {
+            ChromaSubsampling::Yuv444 => ChromaSampling::Cs444,
+            ChromaSubsampling::Yuv422 => ChromaSampling::Cs422,
+            ChromaSubsampling::Yuv420 => ChromaSampling::Cs420,
+        }
+    }
+
+    /// The lowest AV1 profile that carries the layout: main for 4:2:0, high for 4:4:4 and
+    /// professional for 4:2:2.
+    fn seq_profile(self) -> u8 {
+        match self {
+            ChromaSubsampling::Yuv444 => 1,
+            ChromaSubsampling::Yuv422 => 2,
+            ChromaSubsampling::Yuv420 => 0,
+        }
+    }
+}
+
+/// Encoder settings of AVIF outputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AvifOptions {
+    /// 1 (smallest) to 100 (best), drives both color and alpha.
+    pub quality: u8,
+    /// 1 (slowest, smallest) to 10 (fastest).
+    pub speed: u8,
+    /// `Srgb` keeps RGB planes, `Bt709` encodes YCbCr.
+    pub color_space: ColorSpace,
+    /// Chroma resolution of YCbCr outputs, RGB planes are always 4:4:4.
+    pub chroma: ChromaSubsampling,
+}
+
+/// Encodes `img` as an 8-bit AVIF through rav1e, embedding `exif` when given.
+///
+/// Transparency is written as a separate monochrome AV1 image, only if any pixel uses it.
+pub fn encode_avif<W: Write>(
+    img: &RgbaImage,
+    options: &AvifOptions,
+    exif: Option<&[u8]>,
+    mut output: W
+) -> ImageResult<()> {
+    let rgb = options.color_space == ColorSpace::Srgb;
+    if rgb && options.chroma != ChromaSubsampling::Yuv444 {
+        return Err(
+            parameter_error(
+                format!("AVIF RGB planes cannot be subsampled, use YCbCr for {} chroma", options.chroma.label())
+            )
+        );
+    }
+    if !(1..=10).contains(&options.speed) {
+        return Err(parameter_error(format!("AVIF speed must be 1 to 10, not {}", options.speed)));
+    }
+
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let quantizer = quality_to_quantizer(options.quality.clamp(1, 100));
+    let matrix = if rgb { MatrixCoefficients::Identity } else { MatrixCoefficients::BT709 };
+    let color_config = EncoderConfig {
+        chroma_sampling: options.chroma.sampling(),
+        color_description: Some(ColorDescription {
+            color_primaries: ColorPrimaries::BT709,
+            transfer_characteristics: TransferCharacteristics::SRGB,
+            matrix_coefficients: matrix,
+        }),
+        ..still_config(width, height, quantizer, options.speed)
+    };
+    let color = encode_av1(&color_config, &color_planes(img, rgb, options.chroma))?;
+
+    let alpha = if img.pixels().any(|pixel| pixel[3] != 255) {
+        let alpha_config = EncoderConfig {
+            chroma_sampling: ChromaSampling::Cs400,
+            ..still_config(width, height, quantizer, options.speed)
+        };
+        let plane: Vec<u8> = img.pixels().map(|pixel| pixel[3]).collect();
+        Some(encode_av1(&alpha_config, &[(plane, width)])?)
+    } else {
+        None
+    };
+
+    let mut avif = Aviffy::new();
+    avif.set_matrix_coefficients(
+        if rgb { constants::MatrixCoefficients::Rgb } else { constants::MatrixCoefficients::Bt709 }
+    )
+        .set_chroma_subsampling(options.chroma.subsampled())
+        .set_seq_profile(options.chroma.seq_profile())
+        .set_width(img.width())
+        .set_height(img.height())
+        .set_bit_depth(8);
+    if let Some(exif) = exif {
+        avif.set_exif(exif.to_vec());
+    }
+    avif.write_slice(&mut output, &color, alpha.as_deref())?;
+    Ok(())
+}
+
+/// Full range, single key frame settings shared by the color and alpha images.
+fn still_config(width: usize, height: usize, quantizer: u8, speed: u8) -> EncoderConfig {
+    EncoderConfig {
+        width,
+        height,
+        time_base: Rational::new(1, 1),
+        bit_depth: 8,
+        pixel_range: PixelRange::Full,
+        still_picture: true,
+        min_key_frame_interval: 0,
+        max_key_frame_interval: 0,
+        quantizer: usize::from(quantizer),
+        min_quantizer: quantizer,
+        tune: Tune::Psychovisual,
+        ..EncoderConfig::with_speed_preset(speed)
+    }
+}
+
+/// The three planes of `img` with their row widths: G, B and R for RGB, otherwise full range
+/// BT.709 Y, Cb and Cr with Cb and Cr averaged down to the `chroma` resolution.
+fn color_planes(img: &RgbaImage, rgb: bool, chroma: ChromaSubsampling) -> [(Vec<u8>, usize); 3] {
+    let width = img.width() as usize;
+    if rgb {
+        let plane = |channel: usize| img.pixels().map(|pixel| pixel[channel]).collect();
+        return [(plane(1), width), (plane(2), width), (plane(0), width)];
+    }
+
+    const KR: f32 = 0.2126;
+    const KB: f32 = 0.0722;
+    let mut luma = Vec::with_capacity(width * (img.height() as usize));
+    let mut cb = Vec::with_capacity(luma.capacity());
+    let mut cr = Vec::with_capacity(luma.capacity());
+    for pixel in img.pixels() {
+        let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(f32::from);
+        let y = KR * r + (1.0 - KR - KB) * g + KB * b;
+        luma.push(y.round() as u8);
+        cb.push((b - y) / (2.0 * (1.0 - KB)) + 128.0);
+        cr.push((r - y) / (2.0 * (1.0 - KR)) + 128.0);
+    }
+
+    let (x_shift, y_shift) = chroma.subsampled();
+    let (x_shift, y_shift) = (usize::from(x_shift), usize::from(y_shift));
+    let chroma_width = (width + x_shift) >> x_shift;
+    let chroma_height = ((img.height() as usize) + y_shift) >> y_shift;
+    let downsample = |full: &[f32]| -> Vec<u8> {
+        let mut plane = Vec::with_capacity(chroma_width * chroma_height);
+        for cy in 0..chroma_height {
+            for cx in 0..chroma_width {
+                // Average the block of source pixels, clipped at the right and bottom edges
+                let (mut sum, mut count) = (0.0, 0.0);
+                for y in (cy << y_shift)..((cy + 1) << y_shift).min(img.height() as usize) {
+                    for x in (cx << x_shift)..((cx + 1) << x_shift).min(width) {
+                        sum += full[y * width + x];
+                        count += 1.0;
+                    }
+                }
+                plane.push((sum / count).round().clamp(0.0, 255.0) as u8);
+            }
+        }
+        plane
+    };
+    [(luma, width), (downsample(&cb), chroma_width), (downsample(&cr), chroma_width)]
+}
+
+/// Encodes one frame made of `planes`, each given with its row width, into an AV1 bitstream.
+fn encode_av1(config: &EncoderConfig, planes: &[(Vec<u8>, usize)]) -> ImageResult<Vec<u8>> {
+    let mut context: Context<u8> = Config::new()
+        .with_encoder_config(config.clone())
+        .new_context()
+        .map_err(encoding_error)?;
+    let mut frame = context.new_frame();
+    for (plane, (data, stride)) in frame.planes.iter_mut().zip(planes) {
+        plane.copy_from_raw_u8(data, *stride, 1);
+    }
+    context.send_frame(frame).map_err(encoding_error)?;
+    context.flush();
+
+    let mut encoded = Vec::new();
+    loop {
+        match context.receive_packet() {
+            Ok(mut packet) => encoded.append(&mut packet.data),
+            Err(EncoderStatus::Encoded) => {}
+            Err(EncoderStatus::LimitReached) => break,
+            Err(e) => return Err(encoding_error(e)),
+        }
+    }
+    Ok(encoded)
+}
+
+/// Maps quality to an AV1 quantizer on the same curve as `ravif`, so a quality reads the same as
+/// before the encoder was driven directly.
+fn quality_to_quantizer(quality: u8) -> u8 {
+    let q = f32::from(quality) / 100.0;
+    let x = if q >= 0.82 {
+        (1.0 - q) * 2.6
+    } else if q > 0.25 {
+        1.0 - 0.125 - q * 0.5
+    } else {
+        1.0 - q
+    };
+    (x * 255.0).round() as u8
+}
+
+fn parameter_error(message: String) -> ImageError {
+    ImageError::Parameter(error::ParameterError::from_kind(error::ParameterErrorKind::Generic(message)))
+}
+
+fn encoding_error(e: impl std::fmt::Display) -> ImageError {
+    ImageError::Encoding(error::EncodingError::new(ImageFormat::Avif.into(), e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use tracing_test::traced_test;
+
+    fn test_image() -> RgbaImage {
+        image::open("tests/images/test.png").unwrap().to_rgba8()
+    }
+
+    /// The flags byte of the `av1C` box: monochrome, then the x and y subsampling bits.
+    fn av1c_flags(encoded: &[u8]) -> u8 {
+        let position = encoded.windows(4).position(|window| window == b"av1C").unwrap();
+        encoded[position + 6]
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_chroma_subsampling_is_signalled() {
+        let img = test_image();
+        let mut sizes = Vec::new();
+        for chroma in [ChromaSubsampling::Yuv444, ChromaSubsampling::Yuv422, ChromaSubsampling::Yuv420] {
+            let options = AvifOptions { quality: 60, speed: 10, color_space: ColorSpace::Bt709, chroma };
+            let mut encoded = Vec::new();
+            encode_avif(&img, &options, None, &mut encoded).unwrap();
+            let (x, y) = chroma.subsampled();
+            assert_eq!((av1c_flags(&encoded) >> 2) & 0b11, (u8::from(x) << 1) | u8::from(y), "{chroma:?}");
+            sizes.push(encoded.len());
+        }
+        assert!(sizes[2] < sizes[0]);
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_odd_sizes_and_alpha() {
+        let img = RgbaImage::from_fn(33, 17, |x, y| Rgba([(x * 7) as u8, (y * 15) as u8, 90, if x < 3 { 0 } else { 255 }]));
+        let options = AvifOptions {
+            quality: 80,
+            speed: 10,
+            color_space: ColorSpace::Bt709,
+            chroma: ChromaSubsampling::Yuv420,
+        };
+        let mut encoded = Vec::new();
+        encode_avif(&img, &options, Some(b"Exif\0\0"), &mut encoded).unwrap();
+        assert!(encoded.windows(4).any(|window| window == b"auxC"));
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_rgb_planes_reject_subsampling() {
+        let options = AvifOptions {
+            quality: 80,
+            speed: 10,
+            color_space: ColorSpace::Srgb,
+            chroma: ChromaSubsampling::Yuv420,
+        };
+        assert!(encode_avif(&test_image(), &options, None, Vec::new()).is_err());
+        let options = AvifOptions { chroma: ChromaSubsampling::Yuv444, ..options };
+        assert!(encode_avif(&test_image(), &options, None, Vec::new()).is_ok());
+    }
+}
@@ -0,0 +1,406 @@
+// src/color_space.rs
+#![allow(dead_code)] // Remove this once you start using the code
+
+use image::{ ColorType, DynamicImage, ImageResult, Rgba32FImage };
+
+use super::transform::{ self, ResizeMode, ResizeOptions };
+
+/// Rec. 709 luma weights, shared by sRGB.
+const LUMA: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+/// D65 reference white in XYZ, used by the Lab conversions.
+const WHITE: [f32; 3] = [0.95047, 1.0, 1.08883];
+
+/// Decodes one sRGB component in `0.0..=1.0` to linear light.
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+/// Encodes one linear light component in `0.0..=1.0` back to sRGB.
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+}
+
+/// Luma-weighted gray value of an sRGB color.
+pub fn luma([r, g, b]: [f32; 3]) -> f32 {
+    LUMA[0] * r + LUMA[1] * g + LUMA[2] * b
+}
+
+/// Hue in degrees (`0.0..360.0`), saturation and value in `0.0..=1.0`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hsv {
+    pub h: f32,
+    pub s: f32,
+    pub v: f32,
+}
+
+/// Hue in degrees (`0.0..360.0`), saturation and lightness in `0.0..=1.0`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hsl {
+    pub h: f32,
+    pub s: f32,
+    pub l: f32,
+}
+
+/// CIE L*a*b* relative to D65, `l` in `0.0..=100.0`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lab {
+    pub l: f32,
+    pub a: f32,
+    pub b: f32,
+}
+
+/// Hue, min and max channel of an RGB triple, the common ground of HSV and HSL.
+fn hue([r, g, b]: [f32; 3]) -> (f32, f32, f32) {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    let h = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    (h, min, max)
+}
+
+/// RGB from a hue and the chroma and offset of a cylindrical model.
+fn from_hue(h: f32, chroma: f32, offset: f32) -> [f32; 3] {
+    let h = h.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - ((h % 2.0) - 1.0).abs());
+    let [r, g, b] = match h as u32 {
+        0 => [chroma, x, 0.0],
+        1 => [x, chroma, 0.0],
+        2 => [0.0, chroma, x],
+        3 => [0.0, x, chroma],
+        4 => [x, 0.0, chroma],
+        _ => [chroma, 0.0, x],
+    };
+    [r + offset, g + offset, b + offset]
+}
+
+impl Hsv {
+    pub fn from_rgb(rgb: [f32; 3]) -> Self {
+        let (h, min, max) = hue(rgb);
+        let s = if max == 0.0 { 0.0 } else { (max - min) / max };
+        Self { h, s, v: max }
+    }
+
+    pub fn to_rgb(self) -> [f32; 3] {
+        let chroma = self.v * self.s;
+        from_hue(self.h, chroma, self.v - chroma)
+    }
+}
+
+impl Hsl {
+    pub fn from_rgb(rgb: [f32; 3]) -> Self {
+        let (h, min, max) = hue(rgb);
+        let l = (max + min) / 2.0;
+        let s = if max == min { 0.0 } else { (max - min) / (1.0 - (2.0 * l - 1.0).abs()) };
+        Self { h, s, l }
+    }
+
+    pub fn to_rgb(self) -> [f32; 3] {
+        let chroma = (1.0 - (2.0 * self.l - 1.0).abs()) * self.s;
+        from_hue(self.h, chroma, self.l - chroma / 2.0)
+    }
+}
+
+impl Lab {
+    /// Converts an sRGB color, going through linear light and XYZ.
+    pub fn from_rgb(rgb: [f32; 3]) -> Self {
+        let [r, g, b] = rgb.map(srgb_to_linear);
+        let xyz = [
+            0.4124564 * r + 0.3575761 * g + 0.1804375 * b,
+            0.2126729 * r + 0.7151522 * g + 0.072175 * b,
+            0.0193339 * r + 0.119192 * g + 0.9503041 * b,
+        ];
+        let f = |t: f32| {
+            const E: f32 = 6.0 / 29.0;
+            if t > E * E * E { t.cbrt() } else { t / (3.0 * E * E) + 4.0 / 29.0 }
+        };
+        let [fx, fy, fz] = [f(xyz[0] / WHITE[0]), f(xyz[1] / WHITE[1]), f(xyz[2] / WHITE[2])];
+        Self { l: 116.0 * fy - 16.0, a: 500.0 * (fx - fy), b: 200.0 * (fy - fz) }
+    }
+
+    /// Converts back to sRGB, clamping colors outside the sRGB gamut.
+    pub fn to_rgb(self) -> [f32; 3] {
+        let f_inv = |t: f32| {
+            const E: f32 = 6.0 / 29.0;
+            if t > E { t * t * t } else { 3.0 * E * E * (t - 4.0 / 29.0) }
+        };
+        let fy = (self.l + 16.0) / 116.0;
+        let [x, y, z] = [
+            WHITE[0] * f_inv(fy + self.a / 500.0),
+            WHITE[1] * f_inv(fy),
+            WHITE[2] * f_inv(fy - self.b / 200.0),
+        ];
+        [
+            3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+            -0.969266 * x + 1.8760108 * y + 0.041556 * z,
+            0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+        ].map(|c| linear_to_srgb(c.clamp(0.0, 1.0)))
+    }
+}
+
+/// Whether `img` holds floating point samples, which are linear light and may exceed `1.0`.
+pub fn is_hdr(img: &DynamicImage) -> bool {
+    matches!(img.color(), ColorType::Rgb32F | ColorType::Rgba32F)
+}
+
+/// Brightest RGB sample of an HDR image, `1.0` when nothing exceeds it or `img` is not HDR.
+pub fn peak(img: &DynamicImage) -> f32 {
+    if !is_hdr(img) {
+        return 1.0;
+    }
+    img.to_rgba32f().pixels().flat_map(|p| [p[0], p[1], p[2]]).fold(1.0, f32::max)
+}
+
+/// Multiplies the RGB samples of an HDR image by `factor`, other images are returned as they are.
+pub fn scale_hdr(img: &DynamicImage, factor: f32) -> DynamicImage {
+    if !is_hdr(img) {
+        return img.clone();
+    }
+    let mut scaled = img.to_rgba32f();
+    for pixel in scaled.pixels_mut() {
+        for c in &mut pixel.0[..3] {
+            *c *= factor;
+        }
+    }
+    to_color_type(DynamicImage::ImageRgba32F(scaled), img.color())
+}
+
+/// Decodes `img` to floating point linear light, alpha is left untouched.
+///
+/// Floating point images are linear already and only change layout.
+pub fn to_linear(img: &DynamicImage) -> Rgba32FImage {
+    let mut linear = img.to_rgba32f();
+    if is_hdr(img) {
+        return linear;
+    }
+    for pixel in linear.pixels_mut() {
+        for c in &mut pixel.0[..3] {
+            *c = srgb_to_linear(*c);
+        }
+    }
+    linear
+}
+
+/// Encodes linear light pixels back to sRGB in the given color type.
+///
+/// Floating point color types keep linear light, including values above `1.0`.
+pub fn from_linear(mut linear: Rgba32FImage, color: ColorType) -> DynamicImage {
+    if matches!(color, ColorType::Rgb32F | ColorType::Rgba32F) {
+        return to_color_type(DynamicImage::ImageRgba32F(linear), color);
+    }
+    for pixel in linear.pixels_mut() {
+        for c in &mut pixel.0[..3] {
+            *c = linear_to_srgb(c.clamp(0.0, 1.0));
+        }
+    }
+    to_color_type(DynamicImage::ImageRgba32F(linear), color)
+}
+
+/// How [`tone_map`] compresses HDR light into the `0.0..=1.0` range of integer formats.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ToneMap {
+    /// Cut everything brighter than `1.0`, highlights blow out.
+    Clamp,
+    /// Reinhard `L / (1 + L)` on luminance, keeps hues and never clips.
+    #[default]
+    Reinhard,
+    /// Narkowicz' fit of the ACES filmic curve, more contrast and saturated highlights.
+    Aces,
+}
+
+impl ToneMap {
+    /// Maps one linear RGB color, scaled to the exposure already, into `0.0..=1.0`.
+    pub fn apply(self, rgb: [f32; 3]) -> [f32; 3] {
+        match self {
+            ToneMap::Clamp => rgb.map(|c| c.clamp(0.0, 1.0)),
+            ToneMap::Reinhard => {
+                let l = luma(rgb);
+                if l <= 0.0 {
+                    return [0.0; 3];
+                }
+                let scale = 1.0 / (1.0 + l);
+                rgb.map(|c| (c * scale).clamp(0.0, 1.0))
+            }
+            ToneMap::Aces => rgb.map(|c| {
+                let c = c.max(0.0);
+                ((c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14)).clamp(0.0, 1.0)
+            }),
+        }
+    }
+}
+
+/// Turns linear HDR `img` into sRGB in `0.0..=1.0`, ready for an integer format.
+///
+/// `exposure` is in stops, every stop doubles the light before `operator` runs.
+pub fn tone_map(img: &DynamicImage, operator: ToneMap, exposure: f32) -> Rgba32FImage {
+    let gain = exposure.exp2();
+    let mut mapped = img.to_rgba32f();
+    for pixel in mapped.pixels_mut() {
+        let [r, g, b] = operator.apply([pixel[0] * gain, pixel[1] * gain, pixel[2] * gain]).map(linear_to_srgb);
+        pixel.0 = [r, g, b, pixel[3].clamp(0.0, 1.0)];
+    }
+    mapped
+}
+
+/// Runs `operation` on `img` in linear light and returns it in the source color type.
+pub fn in_linear_light(
+    img: &DynamicImage,
+    operation: impl FnOnce(DynamicImage) -> DynamicImage
+) -> DynamicImage {
+    let linear = DynamicImage::ImageRgba32F(to_linear(img));
+    from_linear(operation(linear).into_rgba32f(), img.color())
+}
+
+/// Like [`transform::resize`], averaging pixels in linear light so downscaled photos keep their
+/// brightness.
+pub fn resize_linear(img: &DynamicImage, options: &ResizeOptions) -> ImageResult<DynamicImage> {
+    // Padding is added afterwards so the background color is not taken as linear light
+    let resample = ResizeOptions {
+        mode: if options.mode == ResizeMode::Contain { ResizeMode::Fit } else { options.mode },
+        ..*options
+    };
+    let linear = DynamicImage::ImageRgba32F(to_linear(img));
+    let resized = from_linear(transform::resize(&linear, &resample)?.into_rgba32f(), img.color());
+    if options.mode == ResizeMode::Contain {
+        let (width, height) = options.target_size((img.width(), img.height()))?;
+        return Ok(transform::pad(&resized, width, height, options.background));
+    }
+    Ok(resized)
+}
+
+/// Gaussian blur in linear light, bright highlights bleed like they do optically.
+pub fn blur_linear(img: &DynamicImage, sigma: f32) -> DynamicImage {
+    in_linear_light(img, |linear| linear.blur(sigma))
+}
+
+/// Luma-weighted grayscale that keeps the alpha channel and the bit depth.
+pub fn grayscale(img: &DynamicImage) -> DynamicImage {
+    let mut gray = img.to_rgba32f();
+    for pixel in gray.pixels_mut() {
+        let y = luma([pixel[0], pixel[1], pixel[2]]);
+        pixel.0[..3].fill(y);
+    }
+    let color = match img.color() {
+        ColorType::Rgb8 | ColorType::L8 => ColorType::L8,
+        ColorType::Rgba8 | ColorType::La8 => ColorType::La8,
+        ColorType::Rgb16 | ColorType::L16 => ColorType::L16,
+        ColorType::Rgba16 | ColorType::La16 => ColorType::La16,
+        color => color, // Float images have no gray variant
+    };
+    to_color_type(DynamicImage::ImageRgba32F(gray), color)
+}
+
+/// Converts `img` to `color`, falling back to RGBA8 for color types `image` cannot produce.
+pub fn to_color_type(img: DynamicImage, color: ColorType) -> DynamicImage {
+    match color {
+        ColorType::L8 => img.to_luma8().into(),
+        ColorType::La8 => img.to_luma_alpha8().into(),
+        ColorType::Rgb8 => img.to_rgb8().into(),
+        ColorType::L16 => img.to_luma16().into(),
+        ColorType::La16 => img.to_luma_alpha16().into(),
+        ColorType::Rgb16 => img.to_rgb16().into(),
+        ColorType::Rgba16 => img.to_rgba16().into(),
+        ColorType::Rgb32F => img.to_rgb32f().into(),
+        ColorType::Rgba32F => img,
+        _ => img.to_rgba8().into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::image_processing::transform::ResizeSize;
+    use image::{ GenericImageView, Luma };
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        assert!(a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-3), "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn test_transfer_functions_round_trip() {
+        assert!((srgb_to_linear(0.5) - 0.214).abs() < 1e-3);
+        for c in [0.0, 0.02, 0.2, 0.5, 0.9, 1.0] {
+            assert!((linear_to_srgb(srgb_to_linear(c)) - c).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_cylindrical_models() {
+        let hsv = Hsv::from_rgb([1.0, 0.0, 0.0]);
+        assert_eq!(hsv, Hsv { h: 0.0, s: 1.0, v: 1.0 });
+        let hsl = Hsl::from_rgb([0.0, 0.0, 0.5]);
+        assert_eq!(hsl, Hsl { h: 240.0, s: 1.0, l: 0.25 });
+        for rgb in [[0.2, 0.4, 0.6], [0.9, 0.1, 0.5], [0.3, 0.3, 0.3]] {
+            assert_close(Hsv::from_rgb(rgb).to_rgb(), rgb);
+            assert_close(Hsl::from_rgb(rgb).to_rgb(), rgb);
+            assert_close(Lab::from_rgb(rgb).to_rgb(), rgb);
+        }
+    }
+
+    #[test]
+    fn test_lab_white_point() {
+        let white = Lab::from_rgb([1.0, 1.0, 1.0]);
+        assert!((white.l - 100.0).abs() < 1e-2 && white.a.abs() < 1e-2 && white.b.abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_linear_resize_keeps_brightness() {
+        // A black and white checkerboard averages to half the light, not half the code value
+        let checkers = DynamicImage::ImageLuma8(
+            image::ImageBuffer::from_fn(64, 64, |x, y| Luma([if (x + y) % 2 == 0 { 255 } else { 0 }]))
+        );
+        let options = ResizeOptions::new(ResizeSize::Pixels { width: 8, height: 8 }, ResizeMode::Fill);
+        let naive = transform::resize(&checkers, &options).unwrap();
+        let linear = resize_linear(&checkers, &options).unwrap();
+        assert_eq!(linear.color(), ColorType::L8);
+        assert_eq!(linear.dimensions(), (8, 8));
+        let center = |img: &DynamicImage| img.to_luma8().get_pixel(4, 4)[0];
+        assert!((120..=136).contains(&center(&naive)));
+        assert!((180..=196).contains(&center(&linear)));
+    }
+
+    #[test]
+    fn test_tone_map() {
+        let hdr = DynamicImage::ImageRgb32F(image::ImageBuffer::from_fn(3, 1, |x, _| {
+            image::Rgb([[0.0, 1.0, 8.0][x as usize]; 3])
+        }));
+        let value = |img: &Rgba32FImage, x: u32| img.get_pixel(x, 0)[0];
+
+        let clamped = tone_map(&hdr, ToneMap::Clamp, 0.0);
+        assert_eq!(value(&clamped, 1), value(&clamped, 2));
+        assert!((value(&clamped, 2) - 1.0).abs() < 1e-5);
+        let reinhard = tone_map(&hdr, ToneMap::Reinhard, 0.0);
+        assert_eq!(value(&reinhard, 0), 0.0);
+        assert!((value(&reinhard, 1) - linear_to_srgb(0.5)).abs() < 1e-5);
+        assert!(value(&reinhard, 2) < 1.0);
+        let aces = tone_map(&hdr, ToneMap::Aces, 0.0);
+        assert!(value(&aces, 1) < value(&aces, 2));
+        // One stop down halves the light before the curve
+        let darker = tone_map(&hdr, ToneMap::Reinhard, -1.0);
+        assert!((value(&darker, 1) - linear_to_srgb(0.5 / 1.5)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_linear_resize_keeps_hdr() {
+        let hdr = DynamicImage::ImageRgb32F(image::ImageBuffer::from_pixel(8, 8, image::Rgb([4.0, 2.0, 0.5])));
+        let options = ResizeOptions::new(ResizeSize::Pixels { width: 2, height: 2 }, ResizeMode::Fill);
+        let resized = resize_linear(&hdr, &options).unwrap();
+        assert_eq!(resized.color(), ColorType::Rgb32F);
+        assert_close(resized.to_rgb32f().get_pixel(1, 1).0, [4.0, 2.0, 0.5]);
+    }
+
+    #[test]
+    fn test_grayscale_keeps_alpha() {
+        let img = DynamicImage::new_rgba8(2, 2);
+        assert_eq!(grayscale(&img).color(), ColorType::La8);
+        assert_eq!(grayscale(&DynamicImage::new_rgb16(2, 2)).color(), ColorType::L16);
+    }
+}
@@ -0,0 +1,396 @@
+// src/formats.rs
+use bitflags::bitflags;
+use image::{
+    codecs::{
+        bmp::BmpEncoder,
+        farbfeld::FarbfeldEncoder,
+        ico::{ IcoEncoder, IcoFrame },
+        pnm::{ PnmEncoder, PnmSubtype, SampleEncoding },
+        qoi::QoiEncoder,
+        tga::TgaEncoder,
+    },
+    error,
+    imageops::{ self, FilterType },
+    ColorType,
+    DynamicImage,
+    ExtendedColorType,
+    ImageError,
+    ImageFormat,
+    ImageResult,
+    RgbaImage,
+};
+use std::{ borrow::Cow, fmt, io::{ Seek, Write }, str::FromStr };
+use tiff::{
+    encoder::{ colortype, Compression, DeflateLevel, TiffEncoder, TiffValue },
+    tags::{ Predictor, Tag },
+    TiffResult,
+};
+
+/// Lossless compression of TIFF outputs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TiffCompression {
+    None,
+    /// Widely supported, the usual choice for TIFF.
+    #[default]
+    Lzw,
+    /// Usually smaller than LZW, but not read by every older tool.
+    Deflate,
+    /// Run length encoding, only small for flat artwork.
+    Packbits,
+}
+
+/// PNM variant written for [`ImageFormat::Pnm`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PnmKind {
+    /// PGM for grayscale, PPM for color and PAM for images with transparency.
+    #[default]
+    Auto,
+    /// 1-bit PBM, thresholded at mid gray.
+    Pbm,
+    /// 8-bit grayscale PGM.
+    Pgm,
+    /// 8-bit color PPM.
+    Ppm,
+    /// PAM, which keeps alpha and 16-bit samples.
+    Pam,
+}
+
+impl PnmKind {
+    /// Whether the variant can store an alpha channel.
+    pub fn supports_alpha(self) -> bool {
+        matches!(self, PnmKind::Auto | PnmKind::Pam)
+    }
+
+    /// File extension of the variant, `pnm` when it depends on the image.
+    pub fn extension(self) -> &'static str {
+        match self {
+            PnmKind::Auto => "pnm",
+            PnmKind::Pbm => "pbm",
+            PnmKind::Pgm => "pgm",
+            PnmKind::Ppm => "ppm",
+            PnmKind::Pam => "pam",
+        }
+    }
+}
+
+bitflags! {
+    /// Square sizes written into an ICO, one PNG frame each.
+    pub struct IcoSizes: u8 {
+        const S16 = 0b0000001;
+        const S24 = 0b0000010;
+        const S32 = 0b0000100;
+        const S48 = 0b0001000;
+        const S64 = 0b0010000;
+        const S128 = 0b0100000;
+        const S256 = 0b1000000;
+    }
+}
+
+/// Side in pixels of every [`IcoSizes`] flag.
+const ICO_SIZES: [(IcoSizes, u32); 7] = [
+    (IcoSizes::S16, 16),
+    (IcoSizes::S24, 24),
+    (IcoSizes::S32, 32),
+    (IcoSizes::S48, 48),
+    (IcoSizes::S64, 64),
+    (IcoSizes::S128, 128),
+    (IcoSizes::S256, 256),
+];
+
+impl IcoSizes {
+    /// The classic favicon set, 16, 32 and 48 pixels.
+    pub const FAVICON: IcoSizes = IcoSizes::S16.union(IcoSizes::S32).union(IcoSizes::S48);
+
+    /// The selected sides in pixels, smallest first.
+    pub fn pixels(self) -> impl Iterator<Item = u32> {
+        ICO_SIZES.into_iter().filter(move |(flag, _)| self.contains(*flag)).map(|(_, side)| side)
+    }
+}
+
+impl Default for IcoSizes {
+    fn default() -> Self {
+        IcoSizes::FAVICON
+    }
+}
+
+/// Parses comma separated sides like `16,32,48`, or `all`.
+impl FromStr for IcoSizes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("all") {
+            return Ok(IcoSizes::all());
+        }
+        let mut sizes = IcoSizes::empty();
+        for side in s.split(',').map(str::trim) {
+            let flag = ICO_SIZES
+                .iter()
+                .find(|(_, pixels)| side.parse() == Ok(*pixels))
+                .map(|(flag, _)| *flag)
+                .ok_or_else(|| format!("Invalid icon size `{side}`, expected 16, 24, 32, 48, 64, 128 or 256"))?;
+            sizes |= flag;
+        }
+        Ok(sizes)
+    }
+}
+
+impl fmt::Display for IcoSizes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sides: Vec<String> = self.pixels().map(|side| side.to_string()).collect();
+        write!(f, "{}", sides.join(","))
+    }
+}
+
+/// Writes `img` as a TIFF with `compression`, keeping 16-bit and float samples.
+///
+/// `icc` is embedded as the ICC profile tag, TIFF outputs carry no other metadata.
+pub fn encode_tiff<W: Write + Seek>(
+    img: &DynamicImage,
+    compression: TiffCompression,
+    icc: Option<&[u8]>,
+    output: W
+) -> ImageResult<()> {
+    let float = matches!(img, DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_));
+    let compression = match compression {
+        TiffCompression::None => Compression::Uncompressed,
+        TiffCompression::Lzw => Compression::Lzw,
+        TiffCompression::Deflate => Compression::Deflate(DeflateLevel::Best),
+        TiffCompression::Packbits => Compression::Packbits,
+    };
+    // Row differences help LZW and Deflate a lot, the encoder has none for float samples
+    let predictor = match compression {
+        Compression::Lzw | Compression::Deflate(_) if !float => Predictor::Horizontal,
+        _ => Predictor::None,
+    };
+    let mut encoder = TiffEncoder::new(output)
+        .map_err(tiff_error)?
+        .with_compression(compression)
+        .with_predictor(predictor);
+    let size = (img.width(), img.height());
+    let written = match img {
+        DynamicImage::ImageLuma8(gray) => write_tiff::<colortype::Gray8, _>(&mut encoder, size, gray, icc),
+        DynamicImage::ImageLuma16(gray) => write_tiff::<colortype::Gray16, _>(&mut encoder, size, gray, icc),
+        DynamicImage::ImageRgb8(rgb) => write_tiff::<colortype::RGB8, _>(&mut encoder, size, rgb, icc),
+        DynamicImage::ImageRgb16(rgb) => write_tiff::<colortype::RGB16, _>(&mut encoder, size, rgb, icc),
+        DynamicImage::ImageRgb32F(rgb) => {
+            write_tiff::<colortype::RGB32Float, _>(&mut encoder, size, rgb, icc)
+        }
+        DynamicImage::ImageRgba32F(rgba) => {
+            write_tiff::<colortype::RGBA32Float, _>(&mut encoder, size, rgba, icc)
+        }
+        DynamicImage::ImageLumaA16(_) | DynamicImage::ImageRgba16(_) => {
+            write_tiff::<colortype::RGBA16, _>(&mut encoder, size, &img.to_rgba16(), icc)
+        }
+        _ => write_tiff::<colortype::RGBA8, _>(&mut encoder, size, &img.to_rgba8(), icc),
+    };
+    written.map_err(tiff_error)
+}
+
+fn write_tiff<C, W>(
+    encoder: &mut TiffEncoder<W>,
+    (width, height): (u32, u32),
+    samples: &[C::Inner],
+    icc: Option<&[u8]>
+) -> TiffResult<()>
+    where C: colortype::ColorType, [C::Inner]: TiffValue, W: Write + Seek
+{
+    let mut image = encoder.new_image::<C>(width, height)?;
+    if let Some(icc) = icc {
+        image.encoder().write_tag(Tag::IccProfile, icc)?;
+    }
+    image.write_data(samples)
+}
+
+fn tiff_error(e: tiff::TiffError) -> ImageError {
+    ImageError::Encoding(error::EncodingError::new(ImageFormat::Tiff.into(), e))
+}
+
+/// Writes `img` as an ICO with one frame per size in `sizes`.
+///
+/// Images that are not square are fitted into each frame with transparent padding.
+pub fn encode_ico<W: Write>(img: &DynamicImage, sizes: IcoSizes, output: W) -> ImageResult<()> {
+    if sizes.is_empty() {
+        return Err(
+            ImageError::Parameter(
+                error::ParameterError::from_kind(
+                    error::ParameterErrorKind::Generic("An icon needs at least one size".to_string())
+                )
+            )
+        );
+    }
+    let icons: Vec<RgbaImage> = sizes
+        .pixels()
+        .map(|side| {
+            let fitted = img.resize(side, side, FilterType::Lanczos3).to_rgba8();
+            let mut icon = RgbaImage::new(side, side);
+            let (x, y) = ((side - fitted.width()) / 2, (side - fitted.height()) / 2);
+            imageops::overlay(&mut icon, &fitted, x.into(), y.into());
+            icon
+        })
+        .collect();
+    let frames = icons
+        .iter()
+        .map(|icon| IcoFrame::as_png(icon.as_raw(), icon.width(), icon.height(), ExtendedColorType::Rgba8))
+        .collect::<ImageResult<Vec<_>>>()?;
+    IcoEncoder::new(output).encode_images(&frames)
+}
+
+/// Writes `img` as the PNM variant `kind`, as text instead of binary samples when `ascii` is set.
+///
+/// PAM is always binary.
+pub fn encode_pnm<W: Write>(img: &DynamicImage, kind: PnmKind, ascii: bool, output: W) -> ImageResult<()> {
+    let encoding = if ascii { SampleEncoding::Ascii } else { SampleEncoding::Binary };
+    let color = img.color();
+    let kind = match kind {
+        PnmKind::Auto if color.has_alpha() => PnmKind::Pam,
+        PnmKind::Auto if !color.has_color() => PnmKind::Pgm,
+        PnmKind::Auto => PnmKind::Ppm,
+        kind => kind,
+    };
+    let (pixels, subtype) = match kind {
+        PnmKind::Pbm => {
+            // The encoder takes 0 for black and 1 for white
+            let mut bits = img.to_luma8();
+            bits.pixels_mut().for_each(|p| p[0] = u8::from(p[0] >= 128));
+            (Cow::Owned(DynamicImage::ImageLuma8(bits)), PnmSubtype::Bitmap(encoding))
+        }
+        PnmKind::Pgm => (Cow::Owned(DynamicImage::ImageLuma8(img.to_luma8())), PnmSubtype::Graymap(encoding)),
+        PnmKind::Ppm | PnmKind::Auto => {
+            (Cow::Owned(DynamicImage::ImageRgb8(img.to_rgb8())), PnmSubtype::Pixmap(encoding))
+        }
+        PnmKind::Pam => {
+            let pixels = match color {
+                ColorType::Rgb32F => Cow::Owned(DynamicImage::ImageRgb16(img.to_rgb16())),
+                ColorType::Rgba32F => Cow::Owned(DynamicImage::ImageRgba16(img.to_rgba16())),
+                _ => Cow::Borrowed(img),
+            };
+            (pixels, PnmSubtype::ArbitraryMap)
+        }
+    };
+    pixels.write_with_encoder(PnmEncoder::new(output).with_subtype(subtype))
+}
+
+/// Writes `img` as a TGA, run length encoded unless `rle` is off.
+pub fn encode_tga<W: Write>(img: &DynamicImage, rle: bool, output: W) -> ImageResult<()> {
+    let encoder = TgaEncoder::new(output);
+    let encoder = if rle { encoder } else { encoder.disable_rle() };
+    narrow(img, true).write_with_encoder(encoder)
+}
+
+/// Writes `img` as a BMP, 32 bits per pixel when it has transparency.
+pub fn encode_bmp<W: Write>(img: &DynamicImage, mut output: W) -> ImageResult<()> {
+    narrow(img, true).write_with_encoder(BmpEncoder::new(&mut output))
+}
+
+/// Writes `img` as a QOI.
+pub fn encode_qoi<W: Write>(img: &DynamicImage, output: W) -> ImageResult<()> {
+    narrow(img, false).write_with_encoder(QoiEncoder::new(output))
+}
+
+/// Writes `img` as a Farbfeld, which always stores 16-bit RGBA.
+pub fn encode_farbfeld<W: Write>(img: &DynamicImage, output: W) -> ImageResult<()> {
+    DynamicImage::ImageRgba16(img.to_rgba16()).write_with_encoder(FarbfeldEncoder::new(output))
+}
+
+/// `img` with 8-bit samples, grayscale only when `gray` allows it and alpha only when present.
+fn narrow(img: &DynamicImage, gray: bool) -> Cow<'_, DynamicImage> {
+    let color = img.color();
+    let narrowed = match (gray && !color.has_color(), color.has_alpha()) {
+        (true, false) if color == ColorType::L8 => return Cow::Borrowed(img),
+        (true, true) if color == ColorType::La8 => return Cow::Borrowed(img),
+        (false, false) if color == ColorType::Rgb8 => return Cow::Borrowed(img),
+        (false, true) if color == ColorType::Rgba8 => return Cow::Borrowed(img),
+        (true, false) => DynamicImage::ImageLuma8(img.to_luma8()),
+        (true, true) => DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+        (false, false) => DynamicImage::ImageRgb8(img.to_rgb8()),
+        (false, true) => DynamicImage::ImageRgba8(img.to_rgba8()),
+    };
+    Cow::Owned(narrowed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use tracing_test::traced_test;
+
+    fn test_image() -> DynamicImage {
+        image::open("tests/images/test.png").unwrap()
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_tiff_compression_is_lossless() {
+        let img = DynamicImage::ImageRgb16(test_image().to_rgb16());
+        let mut sizes = Vec::new();
+        for compression in [TiffCompression::None, TiffCompression::Lzw, TiffCompression::Deflate, TiffCompression::Packbits] {
+            let mut encoded = Cursor::new(Vec::new());
+            encode_tiff(&img, compression, Some(b"icc"), &mut encoded).unwrap();
+            let decoded = image::load_from_memory_with_format(encoded.get_ref(), ImageFormat::Tiff).unwrap();
+            assert_eq!(decoded, img, "{compression:?}");
+            sizes.push(encoded.into_inner().len());
+        }
+        assert!(sizes[1] < sizes[0] && sizes[2] < sizes[0]);
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_ico_has_a_frame_per_size() {
+        let mut encoded = Cursor::new(Vec::new());
+        encode_ico(&test_image(), "16,32,256".parse().unwrap(), &mut encoded).unwrap();
+        let icon = encoded.into_inner();
+        // ICONDIR: reserved, type 1, image count
+        assert_eq!(&icon[..6], &[0, 0, 1, 0, 3, 0]);
+        // The largest frame is picked when decoding, 256 is stored as 0
+        let decoded = image::load_from_memory_with_format(&icon, ImageFormat::Ico).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (256, 256));
+        assert!(encode_ico(&test_image(), IcoSizes::empty(), Cursor::new(Vec::new())).is_err());
+    }
+
+    #[test]
+    fn test_parse_ico_sizes() {
+        assert_eq!("16,32,48".parse::<IcoSizes>(), Ok(IcoSizes::FAVICON));
+        assert_eq!("all".parse::<IcoSizes>().unwrap().pixels().count(), 7);
+        assert!("16,20".parse::<IcoSizes>().is_err());
+        assert_eq!(IcoSizes::FAVICON.to_string(), "16,32,48");
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_pnm_kinds() {
+        let img = test_image();
+        let encode = |kind, ascii| {
+            let mut encoded = Vec::new();
+            encode_pnm(&img, kind, ascii, &mut encoded).unwrap();
+            encoded
+        };
+        assert!(encode(PnmKind::Auto, false).starts_with(b"P7"));
+        assert!(encode(PnmKind::Ppm, false).starts_with(b"P6"));
+        assert!(encode(PnmKind::Pgm, true).starts_with(b"P2"));
+
+        let white = DynamicImage::ImageLuma8(image::GrayImage::from_pixel(4, 4, image::Luma([255])));
+        let mut encoded = Vec::new();
+        encode_pnm(&white, PnmKind::Pbm, false, &mut encoded).unwrap();
+        assert!(encoded.starts_with(b"P4"));
+        let decoded = image::load_from_memory_with_format(&encoded, ImageFormat::Pnm).unwrap();
+        assert!(decoded.to_luma8().pixels().all(|p| p[0] == 255));
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_simple_formats_round_trip() {
+        let img = test_image();
+        let rgba = img.to_rgba8();
+        for format in [ImageFormat::Bmp, ImageFormat::Qoi, ImageFormat::Tga, ImageFormat::Farbfeld] {
+            let mut encoded = Vec::new();
+            match format {
+                ImageFormat::Bmp => encode_bmp(&img, &mut encoded),
+                ImageFormat::Qoi => encode_qoi(&img, &mut encoded),
+                ImageFormat::Tga => encode_tga(&img, true, &mut encoded),
+                _ => encode_farbfeld(&img, &mut encoded),
+            }.unwrap();
+            let decoded = image::load_from_memory_with_format(&encoded, format).unwrap();
+            assert_eq!(decoded.to_rgba8(), rgba, "{format:?}");
+        }
+    }
+}
@@ -0,0 +1,399 @@
+// src/metadata.rs
+use image::{
+    error,
+    metadata::Orientation,
+    DynamicImage,
+    ImageDecoder,
+    ImageError,
+    ImageFormat,
+    ImageReader,
+    ImageResult,
+};
+use img_parts::{
+    jpeg::{ markers, Jpeg, JpegSegment },
+    png::{ Png, PngChunk },
+    riff::{ RiffChunk, RiffContent },
+    webp::{ WebP, CHUNK_ALPH, CHUNK_EXIF, CHUNK_ICCP, CHUNK_VP8L, CHUNK_VP8X, CHUNK_XMP },
+    Bytes,
+    ImageICC,
+};
+use std::{ io::Cursor, path::Path };
+use tracing::{ debug, warn };
+
+
+const EXIF_PREFIX: &[u8] = b"Exif\0\0";
+const XMP_NAMESPACE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const ICC_PREFIX: &[u8] = b"ICC_PROFILE\0";
+/// Largest ICC slice that fits one APP2 segment next to its prefix and sequence bytes.
+const ICC_SEGMENT_SIZE: usize = 65519;
+
+/// Which source metadata is carried over to the output.
+///
+/// JPEG, PNG and WebP outputs store all of it. AVIF outputs keep the EXIF only, because the AVIF
+/// encoder cannot write an ICC profile or XMP, and TIFF outputs keep the ICC profile only. What is
+/// dropped that way is reported as a warning, see [`Metadata::dropped_by`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MetadataPolicy {
+    /// Copy EXIF, ICC and XMP as they are.
+    #[default]
+    KeepAll,
+    /// Write pixels only.
+    StripAll,
+    /// Keep the ICC profile and the EXIF copyright and artist, drop everything else.
+    CopyrightIcc,
+    /// Keep everything except GPS location data.
+    StripGps,
+}
+
+impl MetadataPolicy {
+    pub fn label(&self) -> &'static str {
+        match self {
+            MetadataPolicy::KeepAll => "keep all",
+            MetadataPolicy::StripAll => "strip all",
+            MetadataPolicy::CopyrightIcc => "copyright + ICC",
+            MetadataPolicy::StripGps => "strip GPS",
+        }
+    }
+}
+
+/// Raw metadata blocks read from a source container.
+///
+/// `exif` holds the TIFF structure without the `Exif\0\0` marker, `icc` the bare profile and `xmp`
+/// the XML packet.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    pub exif: Option<Vec<u8>>,
+    pub icc: Option<Vec<u8>>,
+    pub xmp: Option<Vec<u8>>,
+}
+
+/// Decodes `path` and reads its metadata in the same pass.
+pub fn open_with_metadata(path: &Path) -> ImageResult<(DynamicImage, Metadata)> {
+    let mut decoder = ImageReader::open(path)?.with_guessed_format()?.into_decoder()?;
+    let metadata = Metadata::from_decoder(&mut decoder)?;
+    let img = DynamicImage::from_decoder(decoder)?;
+    Ok((img, metadata))
+}
+
+/// Reads the metadata of `path` without decoding its pixels, e.g. for animations whose frames
+/// are decoded separately.
+pub fn read_metadata(path: &Path) -> ImageResult<Metadata> {
+    let mut decoder = ImageReader::open(path)?.with_guessed_format()?.into_decoder()?;
+    Metadata::from_decoder(&mut decoder)
+}
+
+impl Metadata {
+    fn from_decoder(decoder: &mut impl ImageDecoder) -> ImageResult<Self> {
+        Ok(Metadata {
+            exif: decoder
+                .exif_metadata()?
+                .map(|exif| exif.strip_prefix(EXIF_PREFIX).map(<[u8]>::to_vec).unwrap_or(exif)),
+            icc: decoder.icc_profile()?,
+            xmp: decoder.xmp_metadata()?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exif.is_none() && self.icc.is_none() && self.xmp.is_none()
+    }
+
+    /// Turns `img` upright according to the EXIF orientation and resets the tag to "no
+    /// transforms", so viewers do not rotate the output a second time.
+    pub fn auto_orient(&mut self, img: &mut DynamicImage) {
+        if let Some(orientation) = self.exif.as_deref_mut().and_then(Orientation::remove_from_exif_chunk) {
+            img.apply_orientation(orientation);
+        }
+    }
+
+    /// The subset of this metadata that `policy` allows in the output.
+    pub fn filtered(&self, policy: MetadataPolicy) -> Metadata {
+        match policy {
+            MetadataPolicy::KeepAll => self.clone(),
+            MetadataPolicy::StripAll => Metadata::default(),
+            MetadataPolicy::CopyrightIcc => Metadata {
+                exif: self.exif.as_deref().and_then(|exif| {
+                    filter_exif(exif, |field| {
+                        field.tag == exif::Tag::Copyright || field.tag == exif::Tag::Artist
+                    })
+                }),
+                icc: self.icc.clone(),
+                xmp: None,
+            },
+            MetadataPolicy::StripGps => Metadata {
+                exif: self.exif.as_deref().and_then(|exif| {
+                    filter_exif(exif, |field| field.tag.context() != exif::Context::Gps)
+                }),
+                icc: self.icc.clone(),
+                // XMP can mirror the EXIF GPS properties, drop the packet rather than edit the XML
+                xmp: self.xmp
+                    .clone()
+                    .filter(|xmp| !xmp.windows(8).any(|window| window == b"exif:GPS")),
+            },
+        }
+    }
+
+    /// A warning naming the blocks `format` cannot store, for the formats that keep only part of
+    /// the metadata, e.g. `ICC and XMP dropped, AVIF keeps EXIF only`.
+    pub fn dropped_by(&self, format: ImageFormat) -> Option<String> {
+        let (unsupported, kept) = match format {
+            ImageFormat::Avif => ([("ICC", &self.icc), ("XMP", &self.xmp)], "AVIF keeps EXIF only"),
+            ImageFormat::Tiff => ([("EXIF", &self.exif), ("XMP", &self.xmp)], "TIFF keeps ICC only"),
+            _ => {
+                return None;
+            }
+        };
+        let dropped: Vec<&str> = unsupported
+            .into_iter()
+            .filter_map(|(name, block)| block.is_some().then_some(name))
+            .collect();
+        (!dropped.is_empty()).then(|| format!("{} dropped, {kept}", dropped.join(" and ")))
+    }
+
+    /// Inserts the metadata into an already encoded JPEG, PNG or WebP file.
+    ///
+    /// AVIF metadata is written by its encoder instead, other formats are returned unchanged.
+    pub fn embed(
+        &self,
+        encoded: Vec<u8>,
+        format: ImageFormat,
+        dimensions: (u32, u32)
+    ) -> ImageResult<Vec<u8>> {
+        if self.is_empty() {
+            return Ok(encoded);
+        }
+        match format {
+            ImageFormat::Jpeg => self.embed_jpeg(encoded),
+            ImageFormat::Png => self.embed_png(encoded),
+            ImageFormat::WebP => self.embed_webp(encoded, dimensions),
+            ImageFormat::Avif | ImageFormat::Tiff => {
+                // Written by the encoders themselves as far as they can, see `Metadata::dropped_by`
+                if let Some(dropped) = self.dropped_by(format) {
+                    debug!("{dropped}");
+                }
+                Ok(encoded)
+            }
+            format => {
+                debug!("Metadata is not written for {:?}", format);
+                Ok(encoded)
+            }
+        }
+    }
+
+    fn embed_jpeg(&self, encoded: Vec<u8>) -> ImageResult<Vec<u8>> {
+        let mut jpeg = Jpeg::from_bytes(encoded.into()).map_err(embed_error(ImageFormat::Jpeg))?;
+        let mut segments = Vec::new();
+        if let Some(exif) = &self.exif {
+            segments.push(
+                JpegSegment::new_with_contents(markers::APP1, [EXIF_PREFIX, exif].concat().into())
+            );
+        }
+        if let Some(xmp) = &self.xmp {
+            segments.push(
+                JpegSegment::new_with_contents(markers::APP1, [XMP_NAMESPACE, xmp].concat().into())
+            );
+        }
+        if let Some(icc) = &self.icc {
+            let count = icc.len().div_ceil(ICC_SEGMENT_SIZE);
+            if count > usize::from(u8::MAX) {
+                warn!("ICC profile is too large for JPEG, dropping it");
+            } else {
+                for (index, chunk) in icc.chunks(ICC_SEGMENT_SIZE).enumerate() {
+                    let contents = [ICC_PREFIX, &[index as u8 + 1, count as u8], chunk].concat();
+                    segments.push(JpegSegment::new_with_contents(markers::APP2, contents.into()));
+                }
+            }
+        }
+        // Metadata segments go right after the JFIF header
+        let at = jpeg.segments()
+            .iter()
+            .take_while(|segment| segment.marker() == markers::APP0)
+            .count();
+        jpeg.segments_mut().splice(at..at, segments);
+        Ok(jpeg.encoder().bytes().to_vec())
+    }
+
+    fn embed_png(&self, encoded: Vec<u8>) -> ImageResult<Vec<u8>> {
+        let mut png = Png::from_bytes(encoded.into()).map_err(embed_error(ImageFormat::Png))?;
+        if let Some(icc) = &self.icc {
+            // An embedded profile overrides the sRGB and gamma hints
+            png.remove_chunks_by_type(*b"sRGB");
+            png.remove_chunks_by_type(*b"gAMA");
+            png.set_icc_profile(Some(Bytes::copy_from_slice(icc)));
+        }
+        let mut chunks = Vec::new();
+        if let Some(exif) = &self.exif {
+            chunks.push(PngChunk::new(*b"eXIf", Bytes::copy_from_slice(exif)));
+        }
+        if let Some(xmp) = &self.xmp {
+            // Uncompressed iTXt with empty language and translated keyword
+            let contents = [b"XML:com.adobe.xmp\0\0\0\0\0".as_slice(), xmp].concat();
+            chunks.push(PngChunk::new(*b"iTXt", contents.into()));
+        }
+        // Readers only look at metadata that precedes the image data
+        let at = png.chunks()
+            .iter()
+            .position(|chunk| &chunk.kind() == b"IDAT")
+            .unwrap_or(png.chunks().len());
+        png.chunks_mut().splice(at..at, chunks);
+        Ok(png.encoder().bytes().to_vec())
+    }
+
+    /// Rebuilds the extended (`VP8X`) header itself, `img-parts` leaves its feature flags stale.
+    fn embed_webp(&self, encoded: Vec<u8>, (width, height): (u32, u32)) -> ImageResult<Vec<u8>> {
+        const ICC: u8 = 0b0010_0000;
+        const ALPHA: u8 = 0b0001_0000;
+        const EXIF: u8 = 0b0000_1000;
+        const XMP: u8 = 0b0000_0100;
+        const ANIMATION: u8 = 0b0000_0010;
+
+        let mut webp = WebP::from_bytes(encoded.into()).map_err(embed_error(ImageFormat::WebP))?;
+        let data = |id| webp.chunk_by_id(id).and_then(|chunk| chunk.content().data()).cloned();
+        let mut flags = data(CHUNK_VP8X).map_or(0, |vp8x| vp8x[0] & (ALPHA | ANIMATION));
+        // The lossless bitstream header stores its own alpha hint in bit 28
+        let lossless_alpha = data(CHUNK_VP8L).is_some_and(|vp8l| vp8l.len() > 4 && vp8l[4] & 0x10 != 0);
+        if webp.has_chunk(CHUNK_ALPH) || lossless_alpha {
+            flags |= ALPHA;
+        }
+        for id in [CHUNK_VP8X, CHUNK_ICCP, CHUNK_EXIF, CHUNK_XMP] {
+            webp.remove_chunks_by_id(id);
+        }
+
+        let chunk = |id, data: &[u8]| RiffChunk::new(id, RiffContent::Data(Bytes::copy_from_slice(data)));
+        let chunks = webp.chunks_mut();
+        if let Some(icc) = &self.icc {
+            flags |= ICC;
+            chunks.insert(0, chunk(CHUNK_ICCP, icc));
+        }
+        if let Some(exif) = &self.exif {
+            flags |= EXIF;
+            chunks.push(chunk(CHUNK_EXIF, exif));
+        }
+        if let Some(xmp) = &self.xmp {
+            flags |= XMP;
+            chunks.push(chunk(CHUNK_XMP, xmp));
+        }
+        let mut vp8x = vec![flags, 0, 0, 0];
+        vp8x.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        vp8x.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+        chunks.insert(0, chunk(CHUNK_VP8X, &vp8x));
+        Ok(webp.encoder().bytes().to_vec())
+    }
+}
+
+/// Rewrites the primary IFD of `exif` with only the fields `keep` accepts.
+///
+/// Thumbnails are dropped, `None` means nothing was left or the block could not be parsed.
+fn filter_exif(exif: &[u8], keep: impl Fn(&exif::Field) -> bool) -> Option<Vec<u8>> {
+    let parsed = match exif::Reader::new().read_raw(exif.to_vec()) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            warn!("Dropping unreadable EXIF block: {e}");
+            return None;
+        }
+    };
+    let fields: Vec<_> = parsed
+        .fields()
+        .filter(|field| field.ifd_num == exif::In::PRIMARY && keep(field))
+        .collect();
+    if fields.is_empty() {
+        return None;
+    }
+
+    let mut writer = exif::experimental::Writer::new();
+    for field in fields {
+        writer.push_field(field);
+    }
+    let mut filtered = Cursor::new(Vec::new());
+    match writer.write(&mut filtered, parsed.little_endian()) {
+        Ok(()) => Some(filtered.into_inner()),
+        Err(e) => {
+            warn!("Dropping EXIF block that could not be rewritten: {e}");
+            None
+        }
+    }
+}
+
+fn embed_error(format: ImageFormat) -> impl FnOnce(img_parts::Error) -> ImageError {
+    move |e| ImageError::Encoding(error::EncodingError::new(format.into(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exif::{ Field, In, Tag, Value };
+
+    fn sample_exif() -> Vec<u8> {
+        let fields = [
+            Field { tag: Tag::Make, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"Swoosh".to_vec()]) },
+            Field { tag: Tag::Copyright, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"Team".to_vec()]) },
+            Field { tag: Tag::GPSLatitudeRef, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"N".to_vec()]) },
+        ];
+        let mut writer = exif::experimental::Writer::new();
+        fields.iter().for_each(|field| writer.push_field(field));
+        let mut buffer = Cursor::new(Vec::new());
+        writer.write(&mut buffer, false).unwrap();
+        buffer.into_inner()
+    }
+
+    fn tags(exif: &[u8]) -> Vec<Tag> {
+        let parsed = exif::Reader::new().read_raw(exif.to_vec()).unwrap();
+        parsed.fields().map(|field| field.tag).collect()
+    }
+
+    fn sample() -> Metadata {
+        Metadata {
+            exif: Some(sample_exif()),
+            icc: Some(b"not a real profile".to_vec()),
+            xmp: Some(b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>".to_vec()),
+        }
+    }
+
+    #[test]
+    fn test_policies_filter_exif() {
+        let metadata = sample();
+        assert_eq!(metadata.filtered(MetadataPolicy::KeepAll), metadata);
+        assert!(metadata.filtered(MetadataPolicy::StripAll).is_empty());
+
+        let no_gps = metadata.filtered(MetadataPolicy::StripGps);
+        assert_eq!(tags(&no_gps.exif.unwrap()), vec![Tag::Make, Tag::Copyright]);
+        assert!(no_gps.xmp.is_some());
+
+        let copyright = metadata.filtered(MetadataPolicy::CopyrightIcc);
+        assert_eq!(tags(&copyright.exif.unwrap()), vec![Tag::Copyright]);
+        assert_eq!(copyright.icc, metadata.icc);
+        assert_eq!(copyright.xmp, None);
+    }
+
+    #[test]
+    fn test_dropped_by() {
+        let metadata = sample();
+        assert_eq!(metadata.dropped_by(ImageFormat::Avif).as_deref(), Some("ICC and XMP dropped, AVIF keeps EXIF only"));
+        assert_eq!(metadata.dropped_by(ImageFormat::Jpeg), None);
+        let exif_only = Metadata { icc: None, xmp: None, ..metadata.clone() };
+        assert_eq!(exif_only.dropped_by(ImageFormat::Avif), None);
+        assert_eq!(exif_only.dropped_by(ImageFormat::Tiff).as_deref(), Some("EXIF dropped, TIFF keeps ICC only"));
+    }
+
+    #[test]
+    fn test_embed_round_trips() {
+        let metadata = sample();
+        let img = DynamicImage::new_rgba8(8, 4);
+        for format in [ImageFormat::Jpeg, ImageFormat::Png, ImageFormat::WebP] {
+            let mut encoded = Cursor::new(Vec::new());
+            let img = if format == ImageFormat::Jpeg { img.to_rgb8().into() } else { img.clone() };
+            img.write_to(&mut encoded, format).unwrap();
+            let embedded = metadata.embed(encoded.into_inner(), format, (8, 4)).unwrap();
+
+            let mut decoder = ImageReader::new(Cursor::new(embedded))
+                .with_guessed_format()
+                .unwrap()
+                .into_decoder()
+                .unwrap();
+            let exif = decoder.exif_metadata().unwrap().unwrap();
+            assert_eq!(exif.strip_prefix(EXIF_PREFIX).unwrap_or(&exif), metadata.exif.as_deref().unwrap());
+            assert_eq!(decoder.icc_profile().unwrap(), metadata.icc, "{format:?}");
+            assert_eq!(decoder.xmp_metadata().unwrap(), metadata.xmp, "{format:?}");
+            assert_eq!(DynamicImage::from_decoder(decoder).unwrap().width(), 8);
+        }
+    }
+}
@@ -1,2 +1,12 @@
+pub mod animation;
+pub mod avif;
+pub mod color_space;
 pub mod compress;
+pub mod formats;
+pub mod metadata;
+pub mod optimize;
+pub mod output_set;
+pub mod pipeline;
+pub mod png_optimizer;
+pub mod quantize;
 pub mod transform;
\ No newline at end of file
@@ -0,0 +1,266 @@
+// src/optimize.rs
+use image::{ error, ImageError, ImageFormat, ImageResult };
+use serde::Deserialize;
+use std::{ fs, path::{ Path, PathBuf } };
+use tracing::{ info, instrument };
+
+use super::{
+    animation,
+    compress::{ encode_image, EncodeOptions },
+    metadata::read_metadata,
+    pipeline::Pipeline,
+};
+use crate::{ config::get_data_dir, output::{ with_suffix, write_atomic } };
+
+/// What happens to the original when [`optimize_in_place`] replaces it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backup {
+    /// No copy is kept.
+    #[default]
+    None,
+    /// Copy the original next to it as `photo.jpg.bak`.
+    Bak,
+    /// Copy the original into the trash directory.
+    Trash,
+}
+
+/// When an optimized file replaces its original, and what is kept of the original.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct OptimizeOptions {
+    /// Smallest saving, in percent of the original size, worth replacing the file for.
+    pub min_savings: f32,
+    pub backup: Backup,
+    /// Directory for [`Backup::Trash`], `trash` in the data directory when unset.
+    pub trash_dir: Option<PathBuf>,
+}
+
+impl Default for OptimizeOptions {
+    fn default() -> Self {
+        Self { min_savings: 1.0, backup: Backup::None, trash_dir: None }
+    }
+}
+
+/// Outcome of [`optimize_in_place`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Optimized {
+    pub original_size: u64,
+    /// Size of the recompressed file, whether or not it replaced the original.
+    pub size: u64,
+    pub replaced: bool,
+    pub backup: Option<PathBuf>,
+}
+
+impl Optimized {
+    /// Bytes saved in percent of the original size, negative when the recompressed file is larger.
+    pub fn savings(&self) -> f64 {
+        if self.original_size == 0 {
+            return 0.0;
+        }
+        (1.0 - (self.size as f64) / (self.original_size as f64)) * 100.0
+    }
+
+    /// Short summary for the CLI and `ImageList`, e.g. `1.2 MB -> 840.3 KB, -30.0%`.
+    pub fn summary(&self) -> String {
+        let (original, size) = (bytesize::ByteSize(self.original_size), bytesize::ByteSize(self.size));
+        if self.replaced {
+            format!("{original} -> {size}, -{:.1}%", self.savings())
+        } else {
+            format!("kept {original}, recompressed {size} ({:+.1}%)", -self.savings())
+        }
+    }
+}
+
+/// Recompresses `path` in its own format with `encoding` and replaces it when the result is at
+/// least `options.min_savings` percent smaller.
+///
+/// `encoding.format` is ignored. Animations keep all of their frames, and animated PNG and WebP
+/// keep their EXIF, ICC and XMP like still images do. The backup is written before the original
+/// is replaced, and the original is untouched when it is kept or anything fails.
+#[instrument(level = "info", skip_all, fields(path = %path.display(), quality = encoding.quality, min_savings = options.min_savings))]
+pub fn optimize_in_place(
+    path: &Path,
+    encoding: &EncodeOptions,
+    options: &OptimizeOptions
+) -> ImageResult<Optimized> {
+    let original_size = fs::metadata(path)?.len();
+    let encoding = EncodeOptions { format: ImageFormat::from_path(path)?, in_place: true, ..*encoding };
+    let encoded = if animation::is_animated(path)? {
+        // Every frame is encoded again, the first alone would replace the animation
+        let frames = animation::decode_frames(path)?;
+        let metadata = read_metadata(path)?.filtered(encoding.metadata);
+        let encoded = animation::encode_animation(&frames, &encoding)?;
+        metadata.embed(encoded, encoding.format, frames[0].buffer().dimensions())?
+    } else {
+        let (img, metadata) = Pipeline::new().decode(path)?;
+        let mut encoded = Vec::new();
+        encode_image(&img, &metadata, &mut encoded, &encoding)?;
+        encoded
+    };
+
+    let size = encoded.len() as u64;
+    let mut optimized = Optimized { original_size, size, replaced: false, backup: None };
+    if optimized.savings() < f64::from(options.min_savings) || optimized.size >= original_size {
+        info!(message = "Original kept", original_size, size = optimized.size);
+        return Ok(optimized);
+    }
+    optimized.backup = backup(path, options)?;
+    write_atomic(path, &encoded)?;
+    optimized.replaced = true;
+    info!(message = "Optimized in place", original_size, size = optimized.size);
+    Ok(optimized)
+}
+
+/// Copies `path` where `options.backup` asks for, never replacing an earlier backup in the trash.
+fn backup(path: &Path, options: &OptimizeOptions) -> ImageResult<Option<PathBuf>> {
+    let file_name = path.file_name().ok_or_else(|| {
+        ImageError::Parameter(
+            error::ParameterError::from_kind(
+                error::ParameterErrorKind::Generic(format!("{} is not a file", path.display()))
+            )
+        )
+    })?;
+    let backup = match options.backup {
+        Backup::None => {
+            return Ok(None);
+        }
+        Backup::Bak => {
+            let mut name = file_name.to_os_string();
+            name.push(".bak");
+            path.with_file_name(name)
+        }
+        Backup::Trash => {
+            let dir = options.trash_dir.clone().unwrap_or_else(|| get_data_dir().join("trash"));
+            fs::create_dir_all(&dir)?;
+            let backup = dir.join(file_name);
+            if backup.exists() {
+                (1..).map(|n| with_suffix(&backup, n)).find(|path| !path.exists()).unwrap_or(backup)
+            } else {
+                backup
+            }
+        }
+    };
+    fs::copy(path, &backup)?;
+    Ok(Some(backup))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::image_processing::metadata::Metadata;
+    use tempfile::tempdir;
+    use tracing_test::traced_test;
+
+    fn copy_of(image: &str, dir: &Path) -> PathBuf {
+        let path = dir.join(image);
+        fs::copy(Path::new("tests/images").join(image), &path).unwrap();
+        path
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_optimize_replaces_smaller_output() {
+        let temp_dir = tempdir().unwrap();
+        let path = copy_of("test.jpg", temp_dir.path());
+        let original = fs::read(&path).unwrap();
+        let options = OptimizeOptions { backup: Backup::Bak, ..OptimizeOptions::default() };
+        let optimized = optimize_in_place(&path, &EncodeOptions::new(ImageFormat::Png, 40), &options).unwrap();
+
+        assert!(optimized.replaced);
+        assert!(optimized.savings() >= 1.0);
+        assert_eq!(fs::metadata(&path).unwrap().len(), optimized.size);
+        // Still a JPEG, whatever format the options asked for
+        let reader = image::ImageReader::open(&path).unwrap().with_guessed_format().unwrap();
+        assert_eq!(reader.format(), Some(ImageFormat::Jpeg));
+        let backup = temp_dir.path().join("test.jpg.bak");
+        assert_eq!(optimized.backup.as_deref(), Some(backup.as_path()));
+        assert_eq!(fs::read(&backup).unwrap(), original);
+        temp_dir.close().unwrap();
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_optimize_keeps_original_below_threshold() {
+        let temp_dir = tempdir().unwrap();
+        let path = copy_of("test.jpg", temp_dir.path());
+        let original = fs::read(&path).unwrap();
+        let options = OptimizeOptions {
+            min_savings: 100.0,
+            backup: Backup::Trash,
+            trash_dir: Some(temp_dir.path().join("trash")),
+        };
+        let optimized = optimize_in_place(&path, &EncodeOptions::new(ImageFormat::Jpeg, 40), &options).unwrap();
+
+        assert!(!optimized.replaced);
+        assert!(optimized.summary().starts_with("kept"));
+        assert_eq!(fs::read(&path).unwrap(), original);
+        assert!(!temp_dir.path().join("trash").exists());
+        temp_dir.close().unwrap();
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_optimize_keeps_every_frame() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("clip.gif");
+        let frames: Vec<_> = [[255, 0, 0, 255], [0, 0, 255, 255]]
+            .into_iter()
+            .map(|color| image::Frame::new(image::RgbaImage::from_pixel(64, 64, image::Rgba(color))))
+            .collect();
+        let options = EncodeOptions::new(ImageFormat::Gif, 40);
+        let mut encoded = animation::encode_animation(&frames, &options).unwrap();
+        // Padding after the trailer, so the encoded file is always smaller
+        encoded.extend([0; 4096]);
+        fs::write(&path, encoded).unwrap();
+        let optimized = optimize_in_place(&path, &options, &OptimizeOptions::default()).unwrap();
+
+        assert!(optimized.replaced);
+        assert_eq!(animation::decode_frames(&path).unwrap().len(), 2);
+        temp_dir.close().unwrap();
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_optimize_keeps_animation_metadata() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("clip.webp");
+        let frames: Vec<_> = (0..2u32)
+            .map(|n| {
+                // Noise, which lossless WebP stores far larger than lossy
+                let noise = |x: u32, y: u32| {
+                    let v = x.wrapping_mul(374_761_393) ^ y.wrapping_mul(668_265_263) ^ n;
+                    (v ^ (v >> 13)).wrapping_mul(1_274_126_177).to_be_bytes()[0]
+                };
+                let pixels = image::RgbaImage::from_fn(64, 64, |x, y| image::Rgba([noise(x, y), noise(y, x), 90, 255]));
+                image::Frame::new(pixels)
+            })
+            .collect();
+        let lossless = EncodeOptions { webp_lossless: true, ..EncodeOptions::new(ImageFormat::WebP, 90) };
+        let metadata = Metadata { icc: Some(b"not a real profile".to_vec()), ..Metadata::default() };
+        let encoded = animation::encode_animation(&frames, &lossless).unwrap();
+        fs::write(&path, metadata.embed(encoded, ImageFormat::WebP, (64, 64)).unwrap()).unwrap();
+
+        let lossy = EncodeOptions::new(ImageFormat::WebP, 20);
+        let optimized = optimize_in_place(&path, &lossy, &OptimizeOptions::default()).unwrap();
+        assert!(optimized.replaced);
+        assert_eq!(animation::decode_frames(&path).unwrap().len(), 2);
+        assert_eq!(read_metadata(&path).unwrap().icc, metadata.icc);
+        temp_dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_trash_backup_never_overwrites() {
+        let temp_dir = tempdir().unwrap();
+        let path = copy_of("test.png", temp_dir.path());
+        let trash_dir = temp_dir.path().join("trash");
+        let options = OptimizeOptions {
+            backup: Backup::Trash,
+            trash_dir: Some(trash_dir.clone()),
+            ..OptimizeOptions::default()
+        };
+        assert_eq!(backup(&path, &options).unwrap(), Some(trash_dir.join("test.png")));
+        assert_eq!(backup(&path, &options).unwrap(), Some(trash_dir.join("test-1.png")));
+        temp_dir.close().unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+// src/output_set.rs
+use image::{ error, ImageError, ImageResult };
+use std::path::{ Path, PathBuf };
+use tracing::{ debug, info, instrument };
+
+use super::{
+    compress::{ write_image, EncodeOptions, EncodeReport },
+    pipeline::Pipeline,
+    transform::{ self, ResizeFilter, ResizeMode, ResizeOptions, ResizeSize },
+};
+use crate::output::{ ensure_not_source, OutputPlan, Planned, Tokens };
+
+/// File names of a `srcset`, e.g. `photo-640w.webp`.
+pub const DEFAULT_TEMPLATE: &str = "{stem}-{width}w.{ext}";
+
+/// Several widths in several formats from one source, the variants of a responsive image.
+///
+/// The source is decoded and run through `pipeline` once, each width is resized once from that
+/// and encoded once per format.
+#[derive(Debug, Clone)]
+pub struct OutputSet {
+    pub widths: Vec<u32>,
+    pub formats: Vec<EncodeOptions>,
+    pub filter: ResizeFilter,
+    pub pipeline: Pipeline,
+    /// Where the variants are written, the template defaults to [`DEFAULT_TEMPLATE`].
+    pub plan: OutputPlan,
+}
+
+/// A file written by [`OutputSet::run`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetOutput {
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub report: EncodeReport,
+}
+
+impl OutputSet {
+    pub fn new(widths: Vec<u32>, formats: Vec<EncodeOptions>) -> Self {
+        Self {
+            widths,
+            formats,
+            filter: ResizeFilter::default(),
+            pipeline: Pipeline::new(),
+            plan: OutputPlan::new(DEFAULT_TEMPLATE),
+        }
+    }
+
+    /// Fails on sets that are empty or whose variants would overwrite each other.
+    pub fn validate(&self) -> ImageResult<()> {
+        let invalid = |message: &str| {
+            Err(
+                ImageError::Parameter(
+                    error::ParameterError::from_kind(error::ParameterErrorKind::Generic(message.to_string()))
+                )
+            )
+        };
+        if self.widths.is_empty() || self.widths.contains(&0) {
+            return invalid("An output set needs one or more non-zero widths");
+        }
+        if self.formats.is_empty() {
+            return invalid("An output set needs one or more formats");
+        }
+        let template = &self.plan.template;
+        if self.widths.len() > 1 && !template.contains("{width}") && !template.contains("{height}") {
+            return invalid("The output set template needs a {width} or {height} placeholder");
+        }
+        if self.formats.len() > 1 && !template.contains("{ext}") {
+            return invalid("The output set template needs an {ext} placeholder");
+        }
+        Ok(())
+    }
+
+    /// Writes every variant of `input_path`, narrowest first, where `plan` puts them.
+    ///
+    /// Images are never enlarged: widths past the source width produce a single variant at the
+    /// source width, named after its actual size. Variants the plan skips are left out.
+    #[instrument(level = "info", skip_all, fields(input_path = %input_path.display(), widths = ?self.widths))]
+    pub fn run(&self, input_path: &Path, index: usize) -> ImageResult<Vec<SetOutput>> {
+        self.validate()?;
+        let (img, metadata) = self.pipeline.decode(input_path)?;
+
+        let mut widths = self.widths.clone();
+        widths.sort_unstable();
+        let mut done = Vec::new();
+        let mut outputs = Vec::new();
+        for width in widths {
+            let options = ResizeOptions {
+                filter: self.filter,
+                ..ResizeOptions::new(ResizeSize::Pixels { width, height: 0 }, ResizeMode::Shrink)
+            };
+            let resized = transform::resize(&img, &options)?;
+            let (width, height) = (resized.width(), resized.height());
+            if done.contains(&width) {
+                continue;
+            }
+            done.push(width);
+            for encoding in &self.formats {
+                let extension = encoding.extension();
+                let tokens = Tokens::new(input_path, extension, (width, height), index);
+                let path = match self.plan.resolve(input_path, &tokens)? {
+                    Planned::Write(path) => path,
+                    Planned::Skip(path) => {
+                        debug!("Skipping existing {path:?}");
+                        continue;
+                    }
+                };
+                ensure_not_source(input_path, &path)?;
+                let report = write_image(&resized, &metadata, &path, encoding)?;
+                outputs.push(SetOutput { path, width, height, report });
+            }
+        }
+        info!(message = "Output set written", files = outputs.len());
+        Ok(outputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::ImageFormat;
+    use tempfile::tempdir;
+    use tracing_test::traced_test;
+
+    #[test]
+    fn test_validate() {
+        let png = EncodeOptions::new(ImageFormat::Png, 90);
+        assert!(OutputSet::new(vec![320, 640], vec![png]).validate().is_ok());
+        assert!(OutputSet::new(vec![], vec![png]).validate().is_err());
+        assert!(OutputSet::new(vec![320, 0], vec![png]).validate().is_err());
+        assert!(OutputSet::new(vec![320], vec![]).validate().is_err());
+
+        let mut set = OutputSet::new(vec![320, 640], vec![png]);
+        set.plan.template = "{stem}.{ext}".to_string();
+        assert!(set.validate().is_err());
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_run_output_set() {
+        let temp_dir = tempdir().unwrap();
+        let input_path = PathBuf::from("tests/images/test.png");
+        let formats = vec![EncodeOptions::new(ImageFormat::WebP, 80), EncodeOptions::new(ImageFormat::Jpeg, 80)];
+        // test.png is 529 pixels wide, 640 and 1280 both collapse into the source width
+        let mut set = OutputSet::new(vec![1280, 64, 640, 320], formats);
+        set.plan.dir = Some(temp_dir.path().to_path_buf());
+        let outputs = set.run(&input_path, 1).unwrap();
+
+        let widths: Vec<u32> = outputs.iter().map(|output| output.width).collect();
+        assert_eq!(widths, [64, 64, 320, 320, 529, 529]);
+        assert_eq!(outputs[0].path, temp_dir.path().join("test-64w.webp"));
+        assert_eq!(outputs[5].path, temp_dir.path().join("test-529w.jpg"));
+        for output in &outputs {
+            let img = image::open(&output.path).unwrap();
+            assert_eq!((img.width(), img.height()), (output.width, output.height));
+        }
+
+        temp_dir.close().unwrap();
+    }
+}
@@ -0,0 +1,351 @@
+// src/pipeline.rs
+use image::{ DynamicImage, ImageResult };
+use std::path::Path;
+
+use super::{
+    color_space,
+    metadata::{ open_with_metadata, Metadata },
+    quantize::{ self, QuantizeOptions },
+    transform::{ self, AspectRatio, Gravity, ResizeOptions, RotateOptions },
+};
+use crate::components::settings::{ SettingsPanel, TransformFlags };
+
+/// A single step of a [`Pipeline`], mirroring the functions in `transform.rs`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    Resize {
+        options: ResizeOptions,
+    },
+    Rotate {
+        degrees: f32,
+        options: RotateOptions,
+    },
+    Flip {
+        horizontal: bool,
+        vertical: bool,
+    },
+    Blur {
+        sigma: f32,
+    },
+    Unsharpen {
+        sigma: f32,
+        threshold: i32,
+    },
+    Crop {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    Brighten {
+        value: i32,
+    },
+    /// Largest `aspect` crop placed by `gravity`, see [`transform::smart_crop_rect`].
+    SmartCrop {
+        aspect: AspectRatio,
+        gravity: Gravity,
+    },
+    /// [`Operation::Resize`] computed in linear light.
+    LinearResize {
+        options: ResizeOptions,
+    },
+    /// [`Operation::Blur`] computed in linear light.
+    LinearBlur {
+        sigma: f32,
+    },
+    Grayscale,
+    Contrast {
+        value: f32,
+    },
+    HueRotate {
+        degrees: i32,
+    },
+    Filter3x3 {
+        kernel: [f32; 9],
+    },
+    /// Crops borders matching the corner color, see [`transform::trim_bounds`].
+    Trim {
+        tolerance: f32,
+    },
+    /// Levels text and edges tilted by up to `max_degrees`, see [`transform::estimate_skew`].
+    Deskew {
+        max_degrees: f32,
+    },
+    /// Reduces the image to `options.colors` colors, see [`quantize::posterize`].
+    Posterize {
+        options: QuantizeOptions,
+    },
+}
+
+impl Operation {
+    pub fn apply(&self, img: DynamicImage) -> ImageResult<DynamicImage> {
+        let img = match *self {
+            Operation::Resize { ref options } => transform::resize(&img, options)?,
+            Operation::Rotate { degrees, ref options } => transform::rotate(&img, degrees, options)?,
+            Operation::Flip { horizontal, vertical } => transform::flip(img, horizontal, vertical),
+            Operation::Blur { sigma } => img.blur(sigma),
+            Operation::Unsharpen { sigma, threshold } => img.unsharpen(sigma, threshold),
+            Operation::Crop { x, y, width, height } => transform::crop(&img, x, y, width, height)?,
+            Operation::SmartCrop { aspect, gravity } => transform::smart_crop(&img, aspect, gravity)?,
+            Operation::Brighten { value } => img.brighten(value),
+            Operation::LinearResize { ref options } => color_space::resize_linear(&img, options)?,
+            Operation::LinearBlur { sigma } => color_space::blur_linear(&img, sigma),
+            Operation::Grayscale => color_space::grayscale(&img),
+            Operation::Contrast { value } => img.adjust_contrast(value),
+            Operation::HueRotate { degrees } => img.huerotate(degrees),
+            Operation::Filter3x3 { kernel } => transform::filter3x3(&img, &kernel),
+            Operation::Trim { tolerance } => transform::trim(&img, tolerance),
+            Operation::Deskew { max_degrees } => transform::deskew(&img, max_degrees)?,
+            Operation::Posterize { ref options } => quantize::posterize(&img, options)?,
+        };
+        Ok(img)
+    }
+}
+
+/// An ordered list of operations applied to one in-memory image.
+///
+/// The source is decoded once, every operation runs on the decoded pixels and the result is
+/// encoded once, so chaining operations does not accumulate generation loss.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    operations: Vec<Operation>,
+    /// Turn the decoded pixels upright from the EXIF orientation before the first operation.
+    auto_orient: bool,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self { operations: Vec::new(), auto_orient: true }
+    }
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(mut self, operation: Operation) -> Self {
+        self.operations.push(operation);
+        self
+    }
+
+    pub fn auto_orient(mut self, enabled: bool) -> Self {
+        self.auto_orient = enabled;
+        self
+    }
+
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+
+    /// Builds a pipeline from the transformations enabled in the settings panel, in list order.
+    pub fn from_settings(settings: &SettingsPanel) -> Self {
+        let mut pipeline = Self::new()
+            .auto_orient(settings.transform_flags.contains(TransformFlags::AUTO_ORIENT));
+        for (_, flag) in &settings.items {
+            if !settings.transform_flags.contains(*flag) {
+                continue;
+            }
+            let operation = match *flag {
+                TransformFlags::RESIZE if settings.linear_light =>
+                    Operation::LinearResize { options: settings.resize_options },
+                TransformFlags::RESIZE => Operation::Resize { options: settings.resize_options },
+                TransformFlags::ROTATE =>
+                    Operation::Rotate {
+                        degrees: settings.rotate_degrees,
+                        options: settings.rotate_options,
+                    },
+                TransformFlags::FLIP =>
+                    Operation::Flip {
+                        horizontal: settings.flip_horizontal,
+                        vertical: settings.flip_vertical,
+                    },
+                TransformFlags::BLUR if settings.linear_light =>
+                    Operation::LinearBlur { sigma: settings.blur_sigma },
+                TransformFlags::BLUR => Operation::Blur { sigma: settings.blur_sigma },
+                TransformFlags::UNSHARPEN =>
+                    Operation::Unsharpen {
+                        sigma: settings.unsharpen_sigma,
+                        threshold: settings.unsharpen_threshold,
+                    },
+                TransformFlags::CROP =>
+                    Operation::Crop {
+                        x: settings.crop_x,
+                        y: settings.crop_y,
+                        width: settings.crop_width,
+                        height: settings.crop_height,
+                    },
+                TransformFlags::SMART_CROP =>
+                    Operation::SmartCrop {
+                        aspect: settings.smart_crop_aspect,
+                        gravity: settings.smart_crop_gravity,
+                    },
+                TransformFlags::BRIGHTEN => Operation::Brighten { value: settings.brighten_value },
+                TransformFlags::FILTER3X3 => Operation::Filter3x3 { kernel: settings.filter3x3_kernel },
+                TransformFlags::CONTRAST => Operation::Contrast { value: settings.contrast_value },
+                TransformFlags::HUEROTATE => Operation::HueRotate { degrees: settings.huerotate_value },
+                TransformFlags::GRAYSCALE => Operation::Grayscale,
+                TransformFlags::DESKEW => Operation::Deskew { max_degrees: settings.deskew_max_degrees },
+                TransformFlags::TRIM => Operation::Trim { tolerance: settings.trim_tolerance },
+                TransformFlags::POSTERIZE => Operation::Posterize { options: settings.posterize_options },
+                TransformFlags::AUTO_ORIENT => {
+                    continue;
+                } // Applied on load, before any operation
+                _ => {
+                    continue;
+                } // Not backed by a transform yet
+            };
+            pipeline.operations.push(operation);
+        }
+        pipeline
+    }
+
+    /// Applies every operation in order to an already decoded image.
+    pub fn apply(&self, img: DynamicImage) -> ImageResult<DynamicImage> {
+        self.operations.iter().try_fold(img, |img, operation| operation.apply(img))
+    }
+
+    /// Decodes `input_path` and runs the pipeline, leaving the encoding to the caller.
+    pub fn decode(&self, input_path: &Path) -> ImageResult<(DynamicImage, Metadata)> {
+        let (mut img, mut metadata) = open_with_metadata(input_path)?;
+        if self.auto_orient {
+            metadata.auto_orient(&mut img);
+        }
+        Ok((self.apply(img)?, metadata))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::image_processing::{
+        compress::{ write_image, EncodeOptions },
+        transform::{ KernelPreset, ResizeMode, ResizeSize },
+    };
+    use image::{ metadata::Orientation, GenericImageView, ImageFormat };
+    use std::{ io::Cursor, path::PathBuf };
+    use tempfile::tempdir;
+    use tracing_test::traced_test;
+
+    #[test]
+    fn test_apply_in_order() {
+        let img = DynamicImage::new_rgba8(40, 20);
+        let pipeline = Pipeline::new()
+            .push(Operation::Rotate { degrees: 90.0, options: RotateOptions::default() })
+            .push(Operation::Crop { x: 0, y: 0, width: 10, height: 30 });
+        let result = pipeline.apply(img.clone()).unwrap();
+        assert_eq!(result.dimensions(), (10, 30));
+
+        // The crop no longer fits once the image is rotated the other way round
+        let pipeline = Pipeline::new().push(Operation::Crop { x: 0, y: 0, width: 10, height: 30 });
+        assert!(pipeline.apply(img).is_err());
+    }
+
+    #[test]
+    fn test_from_settings_uses_enabled_flags() {
+        let mut settings = SettingsPanel::new();
+        settings.transform_flags = TransformFlags::RESIZE | TransformFlags::BLUR;
+        let pipeline = Pipeline::from_settings(&settings);
+        assert_eq!(pipeline.operations(), &[
+            Operation::Resize { options: ResizeOptions::default() },
+            Operation::Blur { sigma: 1.0 },
+        ]);
+
+        settings.transform_flags |= TransformFlags::GRAYSCALE;
+        settings.linear_light = true;
+        let pipeline = Pipeline::from_settings(&settings);
+        assert_eq!(pipeline.operations(), &[
+            Operation::LinearResize { options: ResizeOptions::default() },
+            Operation::LinearBlur { sigma: 1.0 },
+            Operation::Grayscale,
+        ]);
+
+        settings.transform_flags = TransformFlags::FILTER3X3 | TransformFlags::CONTRAST | TransformFlags::HUEROTATE;
+        let pipeline = Pipeline::from_settings(&settings);
+        assert_eq!(pipeline.operations(), &[
+            Operation::Filter3x3 { kernel: KernelPreset::Sharpen.kernel() },
+            Operation::Contrast { value: 0.0 },
+            Operation::HueRotate { degrees: 0 },
+        ]);
+
+        // Deskew runs before trimming so the borders it leaves are trimmed too
+        settings.transform_flags = TransformFlags::TRIM | TransformFlags::DESKEW;
+        let pipeline = Pipeline::from_settings(&settings);
+        assert_eq!(pipeline.operations(), &[
+            Operation::Deskew { max_degrees: 15.0 },
+            Operation::Trim { tolerance: 10.0 },
+        ]);
+
+        // Posterize is last in the list so the other operations cannot add colors back
+        settings.transform_flags = TransformFlags::POSTERIZE | TransformFlags::GRAYSCALE;
+        let pipeline = Pipeline::from_settings(&settings);
+        assert_eq!(pipeline.operations(), &[
+            Operation::Grayscale,
+            Operation::Posterize { options: settings.posterize_options },
+        ]);
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_run_pipeline() {
+        let temp_dir = tempdir().unwrap();
+        let input_path = PathBuf::from("tests/images/test.png");
+        let output_path = temp_dir.path().join("test_image.jpeg");
+        let pipeline = Pipeline::new()
+            .push(Operation::Resize {
+                options: ResizeOptions::new(ResizeSize::Pixels { width: 64, height: 48 }, ResizeMode::Fill),
+            })
+            .push(Operation::Unsharpen { sigma: 1.0, threshold: 1 });
+        let (img, metadata) = pipeline.decode(&input_path).unwrap();
+        write_image(&img, &metadata, &output_path, &EncodeOptions::new(ImageFormat::Jpeg, 90)).unwrap();
+        assert_eq!(image::open(&output_path).unwrap().dimensions(), (64, 48));
+
+        temp_dir.close().unwrap();
+    }
+
+    /// Writes a 40x20 JPEG whose EXIF asks viewers to rotate it 90 degrees clockwise.
+    fn sideways_jpeg(path: &Path) {
+        let field = exif::Field {
+            tag: exif::Tag::Orientation,
+            ifd_num: exif::In::PRIMARY,
+            value: exif::Value::Short(vec![6]),
+        };
+        let mut writer = exif::experimental::Writer::new();
+        writer.push_field(&field);
+        let mut exif = Cursor::new(Vec::new());
+        writer.write(&mut exif, false).unwrap();
+
+        let mut encoded = Cursor::new(Vec::new());
+        DynamicImage::new_rgb8(40, 20).write_to(&mut encoded, ImageFormat::Jpeg).unwrap();
+        let metadata = Metadata { exif: Some(exif.into_inner()), ..Metadata::default() };
+        let encoded = metadata.embed(encoded.into_inner(), ImageFormat::Jpeg, (40, 20)).unwrap();
+        std::fs::write(path, encoded).unwrap();
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_auto_orient() {
+        let temp_dir = tempdir().unwrap();
+        let input_path = temp_dir.path().join("sideways.jpeg");
+        let upright_path = temp_dir.path().join("upright.png");
+        let raw_path = temp_dir.path().join("raw.png");
+        sideways_jpeg(&input_path);
+        let options = EncodeOptions::new(ImageFormat::Png, 90);
+
+        let (img, metadata) = Pipeline::new().decode(&input_path).unwrap();
+        write_image(&img, &metadata, &upright_path, &options).unwrap();
+        let (img, metadata) = open_with_metadata(&upright_path).unwrap();
+        assert_eq!(img.dimensions(), (20, 40));
+        let orientation = Orientation::from_exif_chunk(&metadata.exif.unwrap());
+        assert_eq!(orientation, Some(Orientation::NoTransforms));
+
+        let (img, metadata) = Pipeline::new().auto_orient(false).decode(&input_path).unwrap();
+        write_image(&img, &metadata, &raw_path, &options).unwrap();
+        let (img, metadata) = open_with_metadata(&raw_path).unwrap();
+        assert_eq!(img.dimensions(), (40, 20));
+        let orientation = Orientation::from_exif_chunk(&metadata.exif.unwrap());
+        assert_eq!(orientation, Some(Orientation::Rotate90));
+
+        temp_dir.close().unwrap();
+    }
+}
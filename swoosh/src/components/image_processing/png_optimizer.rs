@@ -0,0 +1,283 @@
+// src/png_optimizer.rs
+use image::{ error, DynamicImage, ImageBuffer, ImageError, ImageFormat, ImageResult, Rgba, RgbaImage };
+use png::{ BitDepth, ColorType, Compression, Filter };
+use std::{ collections::{ HashMap, HashSet }, io::Write };
+use tracing::debug;
+
+/// Row filters tried by [`encode_png`].
+const FILTERS: [Filter; 7] = [
+    Filter::NoFilter,
+    Filter::Sub,
+    Filter::Up,
+    Filter::Avg,
+    Filter::Paeth,
+    Filter::Adaptive,
+    Filter::MinEntropy,
+];
+
+/// Pixels in the smallest PNG layout that stores them losslessly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Reduced {
+    color: ColorType,
+    depth: BitDepth,
+    /// Packed rows, without filter bytes.
+    data: Vec<u8>,
+    /// `PLTE` entries for [`ColorType::Indexed`].
+    palette: Vec<u8>,
+    /// `tRNS` alpha of the leading palette entries.
+    trns: Vec<u8>,
+}
+
+/// Writes `img` as the smallest PNG this encoder finds, losslessly for 8 and 16-bit images.
+///
+/// 16-bit images stay at 16 bits unless every sample is an exact 8-bit value. The color type is
+/// reduced to gray, gray-alpha, RGB or a palette of at most 256 colors whenever the pixels allow
+/// it, fully opaque alpha is dropped and gray and palette samples are packed down to 1, 2 or 4
+/// bits. With `optimize` every row filter strategy is tried at the balanced compression level and
+/// the smallest one is written at the highest, otherwise one adaptive pass is written directly.
+pub fn encode_png<W: Write>(img: &DynamicImage, optimize: bool, output: W) -> ImageResult<()> {
+    let sixteen_bit = matches!(
+        img,
+        DynamicImage::ImageLuma16(_) |
+            DynamicImage::ImageLumaA16(_) |
+            DynamicImage::ImageRgb16(_) |
+            DynamicImage::ImageRgba16(_)
+    );
+    let deep = sixteen_bit.then(|| img.to_rgba16()).filter(|rgba| rgba.iter().any(|&sample| sample % 257 != 0));
+    let reduced = match deep {
+        Some(rgba) => reduce16(&rgba),
+        None => reduce(&img.to_rgba8()),
+    };
+    let (width, height) = (img.width(), img.height());
+    if !optimize {
+        debug!(message = "PNG layout", color = ?reduced.color, depth = ?reduced.depth);
+        return write_png(&reduced, (width, height), Filter::Adaptive, Compression::Balanced, output);
+    }
+    let mut smallest = None;
+    for filter in FILTERS {
+        let mut encoded = Vec::new();
+        write_png(&reduced, (width, height), filter, Compression::Balanced, &mut encoded)?;
+        if smallest.is_none_or(|(_, size)| encoded.len() < size) {
+            smallest = Some((filter, encoded.len()));
+        }
+    }
+    let filter = smallest.map_or(Filter::Adaptive, |(filter, _)| filter);
+    debug!(message = "PNG layout", color = ?reduced.color, depth = ?reduced.depth, filter = ?filter);
+    write_png(&reduced, (width, height), filter, Compression::High, output)
+}
+
+fn write_png<W: Write>(
+    reduced: &Reduced,
+    (width, height): (u32, u32),
+    filter: Filter,
+    compression: Compression,
+    output: W
+) -> ImageResult<()> {
+    let mut encoder = png::Encoder::new(output, width, height);
+    encoder.set_color(reduced.color);
+    encoder.set_depth(reduced.depth);
+    if reduced.color == ColorType::Indexed {
+        encoder.set_palette(reduced.palette.clone());
+        if !reduced.trns.is_empty() {
+            encoder.set_trns(reduced.trns.clone());
+        }
+    }
+    encoder.set_compression(compression);
+    encoder.set_filter(filter);
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    writer.write_image_data(&reduced.data).map_err(png_error)?;
+    writer.finish().map_err(png_error)
+}
+
+fn png_error(e: png::EncodingError) -> ImageError {
+    ImageError::Encoding(error::EncodingError::new(ImageFormat::Png.into(), e))
+}
+
+/// Picks the layout: opaque gray first, then a palette, then gray-alpha, RGB and RGBA.
+fn reduce(img: &RgbaImage) -> Reduced {
+    let opaque = img.pixels().all(|p| p[3] == u8::MAX);
+    let gray = img.pixels().all(|p| p[0] == p[1] && p[1] == p[2]);
+    let width = img.width() as usize;
+    let reduced = |color, depth, data, palette, trns| Reduced { color, depth, data, palette, trns };
+
+    if opaque && gray {
+        let depth = [1, 2, 4]
+            .into_iter()
+            .find(|&depth| img.pixels().all(|p| p[0] % gray_step(depth) == 0))
+            .unwrap_or(8);
+        let samples: Vec<u8> = img.pixels().map(|p| p[0] / gray_step(depth)).collect();
+        return reduced(ColorType::Grayscale, bit_depth(depth), pack(&samples, width, depth), vec![], vec![]);
+    }
+    if let Some(mut colors) = palette(img) {
+        // Transparent entries first, so tRNS can stop at the last one of them
+        colors.sort_by_key(|color| (color[3] == u8::MAX, *color));
+        let index: HashMap<[u8; 4], u8> = colors.iter().enumerate().map(|(i, color)| (*color, i as u8)).collect();
+        let depth = match colors.len() {
+            0..=2 => 1,
+            3..=4 => 2,
+            5..=16 => 4,
+            _ => 8,
+        };
+        let samples: Vec<u8> = img.pixels().map(|p| index[&p.0]).collect();
+        let entries = colors.iter().flat_map(|color| [color[0], color[1], color[2]]).collect();
+        let trns = colors.iter().map(|color| color[3]).take_while(|&alpha| alpha < u8::MAX).collect();
+        return reduced(ColorType::Indexed, bit_depth(depth), pack(&samples, width, depth), entries, trns);
+    }
+    let (color, data) = match (gray, opaque) {
+        (true, _) => (ColorType::GrayscaleAlpha, img.pixels().flat_map(|p| [p[0], p[3]]).collect()),
+        (false, true) => (ColorType::Rgb, img.pixels().flat_map(|p| [p[0], p[1], p[2]]).collect()),
+        (false, false) => (ColorType::Rgba, img.as_raw().clone()),
+    };
+    reduced(color, BitDepth::Eight, data, vec![], vec![])
+}
+
+/// Like [`reduce`] for samples that need all 16 bits, only gray and opaque images are reduced.
+fn reduce16(img: &ImageBuffer<Rgba<u16>, Vec<u16>>) -> Reduced {
+    let opaque = img.pixels().all(|p| p[3] == u16::MAX);
+    let gray = img.pixels().all(|p| p[0] == p[1] && p[1] == p[2]);
+    let (color, channels): (_, &[usize]) = match (gray, opaque) {
+        (true, true) => (ColorType::Grayscale, &[0]),
+        (true, false) => (ColorType::GrayscaleAlpha, &[0, 3]),
+        (false, true) => (ColorType::Rgb, &[0, 1, 2]),
+        (false, false) => (ColorType::Rgba, &[0, 1, 2, 3]),
+    };
+    let data = img
+        .pixels()
+        .flat_map(|p| channels.iter().flat_map(|&channel| p[channel].to_be_bytes()))
+        .collect();
+    Reduced { color, depth: BitDepth::Sixteen, data, palette: vec![], trns: vec![] }
+}
+
+/// The distinct colors of `img`, `None` past 256.
+fn palette(img: &RgbaImage) -> Option<Vec<[u8; 4]>> {
+    let mut colors = Vec::new();
+    let mut seen = HashSet::new();
+    for pixel in img.pixels() {
+        if seen.insert(pixel.0) {
+            if colors.len() == 256 {
+                return None;
+            }
+            colors.push(pixel.0);
+        }
+    }
+    Some(colors)
+}
+
+/// Distance between 8-bit gray levels that survive a reduction to `depth` bits.
+fn gray_step(depth: u8) -> u8 {
+    u8::MAX / ((1u16 << depth) - 1) as u8
+}
+
+fn bit_depth(depth: u8) -> BitDepth {
+    match depth {
+        1 => BitDepth::One,
+        2 => BitDepth::Two,
+        4 => BitDepth::Four,
+        _ => BitDepth::Eight,
+    }
+}
+
+/// Packs `depth`-bit samples into bytes, most significant first, every row starting on a byte.
+fn pack(samples: &[u8], width: usize, depth: u8) -> Vec<u8> {
+    if depth == 8 || width == 0 {
+        return samples.to_vec();
+    }
+    let per_byte = usize::from(8 / depth);
+    samples
+        .chunks(width)
+        .flat_map(|row| row.chunks(per_byte))
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0u8, |byte, (i, &sample)| byte | (sample << (8 - usize::from(depth) * (i + 1))))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ GenericImageView, ImageEncoder };
+
+    fn roundtrip(img: &RgbaImage) -> (Vec<u8>, DynamicImage) {
+        let mut encoded = Vec::new();
+        encode_png(&DynamicImage::ImageRgba8(img.clone()), true, &mut encoded).unwrap();
+        let decoded = image::load_from_memory_with_format(&encoded, ImageFormat::Png).unwrap();
+        (encoded, decoded)
+    }
+
+    #[test]
+    fn test_reduce_layouts() {
+        let black_and_white = RgbaImage::from_fn(9, 3, |x, _| {
+            let level = (x % 2 * 255) as u8;
+            Rgba([level, level, level, 255])
+        });
+        let reduced = reduce(&black_and_white);
+        assert_eq!((reduced.color, reduced.depth), (ColorType::Grayscale, BitDepth::One));
+        // 9 pixels at 1 bit are 2 bytes per row
+        assert_eq!(reduced.data, [0b0101_0101, 0, 0b0101_0101, 0, 0b0101_0101, 0]);
+
+        let four_colors = RgbaImage::from_fn(4, 4, |x, _| Rgba([x as u8 * 80, 0, 0, if x == 0 { 0 } else { 255 }]));
+        let reduced = reduce(&four_colors);
+        assert_eq!((reduced.color, reduced.depth), (ColorType::Indexed, BitDepth::Two));
+        assert_eq!(reduced.trns, [0]);
+
+        let gradient = RgbaImage::from_fn(32, 32, |x, y| Rgba([x as u8, y as u8, 7, 255]));
+        assert_eq!(reduce(&gradient).color, ColorType::Rgb);
+        let translucent = RgbaImage::from_fn(32, 32, |x, y| Rgba([x as u8, y as u8, 7, 128]));
+        assert_eq!(reduce(&translucent).color, ColorType::Rgba);
+        let gray_alpha = RgbaImage::from_fn(32, 32, |x, y| Rgba([x as u8, x as u8, x as u8, y as u8]));
+        assert_eq!(reduce(&gray_alpha).color, ColorType::GrayscaleAlpha);
+    }
+
+    #[test]
+    fn test_encode_png_is_lossless() {
+        let images = [
+            RgbaImage::from_fn(37, 11, |x, y| Rgba([(x * 7) as u8, (y * 13) as u8, 200, ((x + y) * 5) as u8])),
+            RgbaImage::from_fn(37, 11, |x, _| Rgba([(x % 3 * 17) as u8, 0, 255, if x % 5 == 0 { 0 } else { 255 }])),
+            RgbaImage::from_fn(37, 11, |x, y| Rgba([(x * y) as u8, (x * y) as u8, (x * y) as u8, 255])),
+        ];
+        for img in images {
+            let (_, decoded) = roundtrip(&img);
+            assert_eq!(decoded.to_rgba8(), img);
+        }
+    }
+
+    #[test]
+    fn test_encode_png_keeps_16_bits() {
+        let deep = image::ImageBuffer::from_fn(16, 4, |x, y| {
+            image::Rgb([x as u16 * 4000 + 1, y as u16 * 300, 77])
+        });
+        let mut encoded = Vec::new();
+        encode_png(&DynamicImage::ImageRgb16(deep.clone()), true, &mut encoded).unwrap();
+        let decoded = image::load_from_memory_with_format(&encoded, ImageFormat::Png).unwrap();
+        assert_eq!(decoded, DynamicImage::ImageRgb16(deep));
+
+        // Exact 8-bit values in a 16-bit buffer gain nothing from the extra bits
+        let shallow = DynamicImage::ImageRgb8(image::RgbImage::from_fn(32, 32, |x, y| image::Rgb([x as u8, y as u8, 9])));
+        let mut encoded = Vec::new();
+        encode_png(&DynamicImage::ImageRgb16(shallow.to_rgb16()), true, &mut encoded).unwrap();
+        assert_eq!(image::load_from_memory(&encoded).unwrap(), shallow);
+    }
+
+    #[test]
+    fn test_encode_png_beats_plain_rgba() {
+        let img = image::open("tests/images/test.png").unwrap();
+        let mut optimized = Vec::new();
+        encode_png(&img, true, &mut optimized).unwrap();
+        let mut plain = Vec::new();
+        image::codecs::png::PngEncoder::new(&mut plain)
+            .write_image(img.to_rgba8().as_raw(), img.width(), img.height(), image::ExtendedColorType::Rgba8)
+            .unwrap();
+        assert!(optimized.len() < plain.len());
+        let decoded = image::load_from_memory(&optimized).unwrap();
+        assert_eq!(decoded.dimensions(), img.dimensions());
+
+        // The single adaptive pass is lossless too, just not smaller
+        let mut fast = Vec::new();
+        encode_png(&img, false, &mut fast).unwrap();
+        assert!(optimized.len() <= fast.len());
+        assert_eq!(image::load_from_memory(&fast).unwrap().to_rgba8(), decoded.to_rgba8());
+    }
+}
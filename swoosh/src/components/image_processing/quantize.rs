@@ -0,0 +1,307 @@
+// src/quantize.rs
+use color_quant::NeuQuant;
+use image::{ error, DynamicImage, Frame, ImageError, ImageFormat, ImageResult, RgbaImage };
+use std::{ collections::HashMap, io::Write, time::Duration };
+
+/// How the colors lost to a palette are spread over neighbouring pixels.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Dither {
+    /// Nearest palette color, flat areas show banding.
+    None,
+    /// Error diffusion, the smoothest gradients but noisy in flat areas.
+    #[default]
+    FloydSteinberg,
+    /// A fixed 4x4 Bayer pattern, compresses better and does not flicker between frames.
+    Ordered,
+}
+
+/// Palette settings of GIF outputs and of [`posterize`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuantizeOptions {
+    /// Palette size, 2 to 256, one of which goes to transparency when the image has any.
+    pub colors: u16,
+    /// NeuQuant sampling, 1 (slowest, best palette) to 30 (fastest).
+    pub speed: u8,
+    pub dither: Dither,
+    /// Pixels with less alpha become fully transparent, the others fully opaque. 0 keeps every
+    /// pixel opaque.
+    pub alpha_threshold: u8,
+}
+
+impl Default for QuantizeOptions {
+    fn default() -> Self {
+        Self { colors: 256, speed: 10, dither: Dither::default(), alpha_threshold: 128 }
+    }
+}
+
+impl QuantizeOptions {
+    pub fn validate(&self) -> ImageResult<()> {
+        if !(2..=256).contains(&self.colors) {
+            return Err(parameter_error(format!("Palette size must be 2 to 256, not {}", self.colors)));
+        }
+        if !(1..=30).contains(&self.speed) {
+            return Err(parameter_error(format!("Quantizer speed must be 1 to 30, not {}", self.speed)));
+        }
+        Ok(())
+    }
+}
+
+/// An image reduced to a palette, one index per pixel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quantized {
+    pub width: u32,
+    pub height: u32,
+    pub palette: Vec<[u8; 3]>,
+    pub indices: Vec<u8>,
+    /// Palette entry of the transparent pixels, if any.
+    pub transparent: Option<u8>,
+}
+
+impl Quantized {
+    /// The palette colors back as pixels, transparent ones as `[0, 0, 0, 0]`.
+    pub fn to_rgba(&self) -> RgbaImage {
+        let pixels = self.indices
+            .iter()
+            .flat_map(|&index| {
+                if Some(index) == self.transparent {
+                    [0, 0, 0, 0]
+                } else {
+                    let [r, g, b] = self.palette[usize::from(index)];
+                    [r, g, b, 255]
+                }
+            })
+            .collect();
+        RgbaImage::from_raw(self.width, self.height, pixels).expect("one index per pixel")
+    }
+}
+
+/// Picks the colors of one image and maps its pixels to them.
+enum Mapper {
+    /// The image already has few enough colors, each keeps its own entry.
+    Exact(HashMap<[u8; 3], u8>),
+    NeuQuant(NeuQuant),
+}
+
+impl Mapper {
+    fn index_of(&self, rgb: [u8; 3]) -> u8 {
+        match self {
+            Mapper::Exact(entries) => entries[&rgb],
+            Mapper::NeuQuant(nq) => nq.index_of(&[rgb[0], rgb[1], rgb[2], 255]) as u8,
+        }
+    }
+}
+
+/// Reduces `img` to at most `options.colors` colors.
+///
+/// Images that already fit are mapped exactly and never dithered; otherwise NeuQuant picks the
+/// palette from the opaque pixels and `options.dither` maps them to it.
+pub fn quantize(img: &RgbaImage, options: &QuantizeOptions) -> ImageResult<Quantized> {
+    options.validate()?;
+    let (width, height) = img.dimensions();
+    let is_transparent = |alpha: u8| alpha < options.alpha_threshold;
+    let opaque: Vec<u8> = img
+        .pixels()
+        .filter(|pixel| !is_transparent(pixel[3]))
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255])
+        .collect();
+    let has_transparency = opaque.len() < img.as_raw().len();
+    let budget = usize::from(options.colors) - usize::from(has_transparency);
+
+    let mut distinct: HashMap<[u8; 3], u8> = HashMap::new();
+    for pixel in opaque.chunks_exact(4) {
+        let next = distinct.len();
+        distinct.entry([pixel[0], pixel[1], pixel[2]]).or_insert(next as u8);
+        if distinct.len() > budget {
+            break;
+        }
+    }
+    let (mapper, mut palette) = if distinct.len() <= budget {
+        let mut palette = vec![[0; 3]; distinct.len()];
+        for (rgb, &index) in &distinct {
+            palette[usize::from(index)] = *rgb;
+        }
+        (Mapper::Exact(distinct), palette)
+    } else {
+        let nq = NeuQuant::new(i32::from(options.speed), budget, &opaque);
+        let palette = nq.color_map_rgb().chunks_exact(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]).collect();
+        (Mapper::NeuQuant(nq), palette)
+    };
+    let transparent = has_transparency.then(|| {
+        palette.push([0; 3]);
+        (palette.len() - 1) as u8
+    });
+
+    let dither = match mapper {
+        Mapper::Exact(_) => Dither::None,
+        Mapper::NeuQuant(_) => options.dither,
+    };
+    let mut indices = Vec::with_capacity(img.as_raw().len() / 4);
+    // Floyd–Steinberg error carried to the current and the next row
+    let row = width as usize + 2;
+    let mut errors = vec![[0i16; 3]; row * 2];
+    for y in 0..height {
+        let (current, next) = errors.split_at_mut(row);
+        for x in 0..width {
+            let pixel = img.get_pixel(x, y);
+            if is_transparent(pixel[3]) {
+                indices.push(transparent.unwrap_or_default());
+                continue;
+            }
+            let mut wanted = [i16::from(pixel[0]), i16::from(pixel[1]), i16::from(pixel[2])];
+            match dither {
+                Dither::None => {}
+                Dither::FloydSteinberg => {
+                    for (channel, error) in wanted.iter_mut().zip(current[x as usize + 1]) {
+                        *channel += error;
+                    }
+                }
+                Dither::Ordered => {
+                    let offset = ordered_offset(x, y, palette.len());
+                    wanted.iter_mut().for_each(|channel| *channel += offset);
+                }
+            }
+            let rgb = wanted.map(|channel| channel.clamp(0, 255) as u8);
+            let index = mapper.index_of(rgb);
+            indices.push(index);
+            if dither == Dither::FloydSteinberg {
+                let chosen = palette[usize::from(index)];
+                let x = x as usize + 1;
+                for channel in 0..3 {
+                    let error = i16::from(rgb[channel]) - i16::from(chosen[channel]);
+                    current[x + 1][channel] += error * 7 / 16;
+                    next[x - 1][channel] += error * 3 / 16;
+                    next[x][channel] += error * 5 / 16;
+                    next[x + 1][channel] += error / 16;
+                }
+            }
+        }
+        errors.copy_within(row.., 0);
+        errors[row..].fill([0; 3]);
+    }
+    Ok(Quantized { width, height, palette, indices, transparent })
+}
+
+/// Threshold offset of the ordered dither at `(x, y)`, spread over the gap between palette
+/// levels of `colors` entries.
+fn ordered_offset(x: u32, y: u32, colors: usize) -> i16 {
+    const BAYER: [[i16; 4]; 4] = [
+        [0, 8, 2, 10],
+        [12, 4, 14, 6],
+        [3, 11, 1, 9],
+        [15, 7, 13, 5],
+    ];
+    let levels = (colors as f32).cbrt().max(2.0);
+    let spread = (255.0 / (levels - 1.0)) as i16;
+    (BAYER[(y % 4) as usize][(x % 4) as usize] * 2 - 15) * spread / 32
+}
+
+/// Reduces `img` to `options.colors` colors, e.g. before writing an 8-bit PNG.
+///
+/// Alpha becomes all or nothing at `options.alpha_threshold`; images without alpha stay RGB.
+pub fn posterize(img: &DynamicImage, options: &QuantizeOptions) -> ImageResult<DynamicImage> {
+    let rgba = quantize(&img.to_rgba8(), options)?.to_rgba();
+    if img.color().has_alpha() {
+        Ok(DynamicImage::ImageRgba8(rgba))
+    } else {
+        Ok(DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(rgba).into_rgb8()))
+    }
+}
+
+/// Writes `frames` as a GIF, each with its own palette, looping forever when there is more than
+/// one.
+pub fn encode_gif<W: Write>(frames: &[Frame], options: &QuantizeOptions, output: W) -> ImageResult<()> {
+    let Some(first) = frames.first() else {
+        return Err(parameter_error("A GIF needs at least one frame".to_string()));
+    };
+    let (width, height) = first.buffer().dimensions();
+    let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(height)) else {
+        return Err(parameter_error(format!("GIF is limited to 65535 pixels a side, not {width}x{height}")));
+    };
+    let mut encoder = gif::Encoder::new(output, width, height, &[]).map_err(gif_error)?;
+    if frames.len() > 1 {
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(gif_error)?;
+    }
+    for frame in frames {
+        let quantized = quantize(frame.buffer(), options)?;
+        let delay = Duration::from(frame.delay()).as_millis() / 10;
+        let frame = gif::Frame {
+            width,
+            height,
+            delay: delay.min(u128::from(u16::MAX)) as u16,
+            // Every frame covers the canvas, so clearing keeps transparent pixels transparent
+            dispose: gif::DisposalMethod::Background,
+            transparent: quantized.transparent,
+            palette: Some(quantized.palette.concat()),
+            buffer: quantized.indices.into(),
+            ..gif::Frame::default()
+        };
+        encoder.write_frame(&frame).map_err(gif_error)?;
+    }
+    Ok(())
+}
+
+fn parameter_error(message: String) -> ImageError {
+    ImageError::Parameter(error::ParameterError::from_kind(error::ParameterErrorKind::Generic(message)))
+}
+
+fn gif_error(e: gif::EncodingError) -> ImageError {
+    ImageError::Encoding(error::EncodingError::new(ImageFormat::Gif.into(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ GenericImageView, Rgba };
+    use std::collections::HashSet;
+    use tracing_test::traced_test;
+
+    fn gradient() -> RgbaImage {
+        RgbaImage::from_fn(64, 16, |x, y| Rgba([(x * 4) as u8, (y * 16) as u8, 128, if x < 4 { 0 } else { 255 }]))
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_quantize_limits_colors() {
+        for dither in [Dither::None, Dither::FloydSteinberg, Dither::Ordered] {
+            let options = QuantizeOptions { colors: 16, dither, ..QuantizeOptions::default() };
+            let quantized = quantize(&gradient(), &options).unwrap();
+            assert!(quantized.palette.len() <= 16, "{dither:?}");
+            assert_eq!(quantized.transparent, Some((quantized.palette.len() - 1) as u8));
+            let rgba = quantized.to_rgba();
+            assert_eq!(rgba.get_pixel(0, 0)[3], 0);
+            assert_eq!(rgba.get_pixel(4, 0)[3], 255);
+            let colors: HashSet<_> = rgba.pixels().collect();
+            assert!(colors.len() <= 16, "{dither:?}");
+        }
+        let options = QuantizeOptions { colors: 300, ..QuantizeOptions::default() };
+        assert!(quantize(&gradient(), &options).is_err());
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_quantize_keeps_few_colors_exact() {
+        let img = RgbaImage::from_fn(8, 8, |x, _| if x < 4 { Rgba([200, 10, 10, 255]) } else { Rgba([10, 10, 200, 255]) });
+        let options = QuantizeOptions { colors: 2, alpha_threshold: 0, ..QuantizeOptions::default() };
+        let quantized = quantize(&img, &options).unwrap();
+        assert_eq!(quantized.transparent, None);
+        assert_eq!(quantized.to_rgba(), img);
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_posterize_and_encode_gif() {
+        let img = image::open("tests/images/test.png").unwrap();
+        let options = QuantizeOptions { colors: 8, dither: Dither::Ordered, ..QuantizeOptions::default() };
+        let posterized = posterize(&img, &options).unwrap();
+        assert_eq!(posterized.dimensions(), img.dimensions());
+        let colors: HashSet<_> = posterized.to_rgba8().pixels().copied().collect();
+        assert!(colors.len() <= 8);
+
+        let mut encoded = Vec::new();
+        encode_gif(&[Frame::new(img.to_rgba8())], &options, &mut encoded).unwrap();
+        let decoded = image::load_from_memory_with_format(&encoded, ImageFormat::Gif).unwrap();
+        assert_eq!(decoded.dimensions(), img.dimensions());
+        let colors: HashSet<_> = decoded.to_rgba8().pixels().copied().collect();
+        assert!(colors.len() <= 8);
+    }
+}
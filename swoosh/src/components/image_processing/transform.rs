@@ -1,61 +1,519 @@
 // src/transform.rs
 use image::{
-    imageops::{self, FilterType}, GenericImageView,  ImageError, ImageResult,
+    error::{ ParameterError, ParameterErrorKind },
+    imageops::FilterType, ColorType, DynamicImage, GenericImageView, GrayImage, ImageError, ImageResult, Rgb, Rgba, Rgba32FImage,
 };
-use std::path::Path;
-use tracing::{instrument, info, error};
-
-#[instrument(level = "info", skip_all, fields(input_path = %input_path.display(), output_path = %output_path.display(), width = width, height = height, preserve_aspect_ratio = preserve_aspect_ratio))]
-pub fn resize_image(
-    input_path: &Path,
-    output_path: &Path,
-    width: u32,
-    height: u32,
-    preserve_aspect_ratio: bool,
-) -> ImageResult<()> {
-    let img = image::open(input_path)?;
-    let resized_img = if preserve_aspect_ratio {
-        let (w, h) = img.dimensions();
-        let ratio = f64::from(w) / f64::from(h);
-        let new_width = if width > 0 { width } else { (f64::from(height) * ratio) as u32 };
-        let new_height = if height > 0 { height } else { (f64::from(width) / ratio) as u32 };
-
-        img.resize(new_width, new_height, FilterType::Lanczos3)
-    } else {
-        img.resize_exact(width, height, FilterType::Lanczos3)
+use std::{ fmt, str::FromStr };
+use tracing::{ info, error };
+
+use super::color_space::{ self, Lab };
+
+/// Common 3x3 convolution kernels for [`filter3x3`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KernelPreset {
+    /// Boosts the center against its neighbours.
+    #[default]
+    Sharpen,
+    /// Keeps only the outlines, flat areas turn black.
+    EdgeDetect,
+    /// Relief lit from the top left.
+    Emboss,
+    /// Averages each pixel with its neighbours.
+    BoxBlur,
+}
+
+impl KernelPreset {
+    pub const ALL: [KernelPreset; 4] = [
+        KernelPreset::Sharpen,
+        KernelPreset::EdgeDetect,
+        KernelPreset::Emboss,
+        KernelPreset::BoxBlur,
+    ];
+
+    /// Row-major weights, top left first.
+    pub fn kernel(self) -> [f32; 9] {
+        match self {
+            KernelPreset::Sharpen => [0.0, -1.0, 0.0, -1.0, 5.0, -1.0, 0.0, -1.0, 0.0],
+            KernelPreset::EdgeDetect => [-1.0, -1.0, -1.0, -1.0, 8.0, -1.0, -1.0, -1.0, -1.0],
+            KernelPreset::Emboss => [-2.0, -1.0, 0.0, -1.0, 1.0, 1.0, 0.0, 1.0, 2.0],
+            KernelPreset::BoxBlur => [1.0; 9],
+        }
+    }
+
+    /// The preset whose weights are exactly `kernel`, if any.
+    pub fn from_kernel(kernel: &[f32; 9]) -> Option<Self> {
+        Self::ALL.into_iter().find(|preset| preset.kernel() == *kernel)
+    }
+}
+
+fn parameter_error(message: String) -> ImageError {
+    ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::Generic(message)))
+}
+
+/// Largest width or height [`resize`] produces, the limit of the JPEG and WebP encoders.
+pub const MAX_DIMENSION: u32 = 65_535;
+
+/// How [`resize`] fits the source into the target box.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ResizeMode {
+    /// Scale to fit inside the box, keeping the aspect ratio.
+    #[default]
+    Fit,
+    /// Stretch to exactly the box, ignoring the aspect ratio.
+    Fill,
+    /// Scale to cover the box, keeping the aspect ratio, and crop the overflow around the center.
+    Cover,
+    /// Fit inside the box and pad the rest with the background color.
+    Contain,
+    /// Like `Fit`, but leave images that already fit untouched instead of enlarging them.
+    Shrink,
+}
+
+/// Resampling filter of [`resize`], from the blockiest and fastest to the sharpest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResizeFilter {
+    /// Copies the closest pixel, keeps pixel art crisp.
+    Nearest,
+    /// Bilinear, quick enough for previews.
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    #[default]
+    Lanczos3,
+}
+
+impl From<ResizeFilter> for FilterType {
+    fn from(filter: ResizeFilter) -> Self {
+        match filter {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// Target box of [`resize`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResizeSize {
+    /// Either side may be 0 to derive it from the other one and the source aspect ratio.
+    Pixels {
+        width: u32,
+        height: u32,
+    },
+    /// Both sides scaled by a percentage, `50.0` halves the image.
+    Percent(f32),
+    /// Both sides scaled to about this many million pixels in total.
+    Megapixels(f32),
+}
+
+impl fmt::Display for ResizeSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ResizeSize::Pixels { width, height } => write!(f, "{width}x{height}"),
+            ResizeSize::Percent(percent) => write!(f, "{percent}%"),
+            ResizeSize::Megapixels(megapixels) => write!(f, "{megapixels} MP"),
+        }
+    }
+}
+
+/// Everything [`resize`] needs besides the image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResizeOptions {
+    pub size: ResizeSize,
+    pub mode: ResizeMode,
+    pub filter: ResizeFilter,
+    /// Padding color of [`ResizeMode::Contain`]. A translucent background adds an alpha channel.
+    pub background: Rgba<u8>,
+}
+
+impl Default for ResizeOptions {
+    fn default() -> Self {
+        Self {
+            size: ResizeSize::Pixels { width: 800, height: 600 },
+            mode: ResizeMode::default(),
+            filter: ResizeFilter::default(),
+            background: Rgba([0, 0, 0, 0]),
+        }
+    }
+}
+
+impl ResizeOptions {
+    pub fn new(size: ResizeSize, mode: ResizeMode) -> Self {
+        Self { size, mode, ..Self::default() }
+    }
+
+    /// The box a `width` x `height` source is resized into, failing on sizes that make no sense.
+    pub fn target_size(&self, (width, height): (u32, u32)) -> ImageResult<(u32, u32)> {
+        if width == 0 || height == 0 {
+            return Err(parameter_error("Cannot resize an empty image".to_string()));
+        }
+        let (source_width, source_height) = (f64::from(width), f64::from(height));
+        let scaled = |scale: f64| {
+            ((source_width * scale).round().max(1.0), (source_height * scale).round().max(1.0))
+        };
+        let (target_width, target_height) = match self.size {
+            ResizeSize::Pixels { width: 0, height: 0 } => {
+                return Err(parameter_error("Resize needs a width, a height or both".to_string()));
+            }
+            ResizeSize::Pixels { width: 0, height: target_height } => {
+                scaled(f64::from(target_height) / source_height)
+            }
+            ResizeSize::Pixels { width: target_width, height: 0 } => {
+                scaled(f64::from(target_width) / source_width)
+            }
+            ResizeSize::Pixels { width, height } => (f64::from(width), f64::from(height)),
+            ResizeSize::Percent(percent) if percent.is_finite() && percent > 0.0 => {
+                scaled(f64::from(percent) / 100.0)
+            }
+            ResizeSize::Megapixels(megapixels) if megapixels.is_finite() && megapixels > 0.0 => {
+                scaled((f64::from(megapixels) * 1e6 / (source_width * source_height)).sqrt())
+            }
+            size => {
+                return Err(parameter_error(format!("Invalid resize size {size}")));
+            }
+        };
+        if target_width > f64::from(MAX_DIMENSION) || target_height > f64::from(MAX_DIMENSION) {
+            return Err(
+                parameter_error(
+                    format!("Resizing to {target_width}x{target_height} exceeds {MAX_DIMENSION} pixels per side")
+                )
+            );
+        }
+        Ok((target_width as u32, target_height as u32))
+    }
+}
+
+/// Scales `img` into `options.size` as `options.mode` says, see [`ResizeOptions::target_size`].
+pub fn resize(img: &DynamicImage, options: &ResizeOptions) -> ImageResult<DynamicImage> {
+    let (width, height) = options.target_size(img.dimensions())?;
+    let filter = options.filter.into();
+    if options.mode == ResizeMode::Shrink && img.width() <= width && img.height() <= height {
+        return Ok(img.clone());
+    }
+    // `image` clamps float samples to 1.0 while resampling, HDR highlights are scaled into range
+    let peak = color_space::peak(img);
+    let normalized = (peak > 1.0).then(|| color_space::scale_hdr(img, 1.0 / peak));
+    let source = normalized.as_ref().unwrap_or(img);
+    let resized = match options.mode {
+        ResizeMode::Fill => source.resize_exact(width, height, filter),
+        ResizeMode::Cover => source.resize_to_fill(width, height, filter),
+        ResizeMode::Fit | ResizeMode::Shrink | ResizeMode::Contain => {
+            let (fit_width, fit_height) = fit_within(img.dimensions(), (width, height));
+            source.resize_exact(fit_width, fit_height, filter)
+        }
     };
+    let resized = if peak > 1.0 { color_space::scale_hdr(&resized, peak) } else { resized };
+    if options.mode == ResizeMode::Contain {
+        return Ok(pad(&resized, width, height, options.background));
+    }
+    Ok(resized)
+}
+
+/// Largest size with the aspect ratio of `source` that fits inside `bounds`.
+fn fit_within((width, height): (u32, u32), (max_width, max_height): (u32, u32)) -> (u32, u32) {
+    let scale = (f64::from(max_width) / f64::from(width)).min(f64::from(max_height) / f64::from(height));
+    let fit = |side: u32, max: u32| ((f64::from(side) * scale).round() as u32).clamp(1, max);
+    (fit(width, max_width), fit(height, max_height))
+}
 
-    resized_img.save(output_path)?;
-    info!(message = "Image resized");
-    Ok(())
+/// Centers `img` on a `width` x `height` canvas filled with `background`.
+pub fn pad(img: &DynamicImage, width: u32, height: u32, background: Rgba<u8>) -> DynamicImage {
+    let fill = background.0.map(|c| f32::from(c) / 255.0);
+    let mut canvas = Rgba32FImage::from_pixel(width, height, Rgba(fill));
+    let x = (i64::from(width) - i64::from(img.width())) / 2;
+    let y = (i64::from(height) - i64::from(img.height())) / 2;
+    image::imageops::overlay(&mut canvas, &img.to_rgba32f(), x, y);
+    let color = if background[3] < u8::MAX { with_alpha(img.color()) } else { img.color() };
+    color_space::to_color_type(DynamicImage::ImageRgba32F(canvas), color)
 }
 
+/// Resampling used by rotations that are not a multiple of 90 degrees.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Interpolation {
+    Nearest,
+    #[default]
+    Bilinear,
+    Bicubic,
+}
 
-#[instrument(level = "info", skip_all, fields(input_path = %input_path.display(), output_path = %output_path.display()))]
-pub fn rotate_image(input_path: &Path, output_path: &Path, degrees: i32) -> ImageResult<()> {
-    let img = image::open(input_path)?;
+/// How [`rotate`] samples the source and fills the area it does not cover.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RotateOptions {
+    pub interpolation: Interpolation,
+    /// Grow the canvas to fit the whole rotated image instead of keeping the original size.
+    pub expand: bool,
+    /// Fill for uncovered areas. A translucent background adds an alpha channel to the output.
+    pub background: Rgba<u8>,
+}
 
-    let rotated_image = match degrees {
-        90 => img.rotate90(),
-        180 => img.rotate180(),
-        270 => img.rotate270(),
-        _ => {
-            error!(message = "Invalid rotation angle", angle = degrees);
-            return Err(ImageError::Parameter(image::error::ParameterError::from_kind(image::error::ParameterErrorKind::Generic(format!("Invalid rotation angle: {}", degrees)))));
+impl Default for RotateOptions {
+    fn default() -> Self {
+        Self {
+            interpolation: Interpolation::default(),
+            expand: true,
+            background: Rgba([0, 0, 0, 0]),
+        }
+    }
+}
+
+/// Rotates `img` clockwise by `degrees`.
+///
+/// Multiples of 90 degrees are lossless pixel moves; any other angle is resampled with
+/// `options.interpolation`.
+pub fn rotate(img: &DynamicImage, degrees: f32, options: &RotateOptions) -> ImageResult<DynamicImage> {
+    if !degrees.is_finite() {
+        error!(message = "Invalid rotation angle", angle = degrees);
+        return Err(parameter_error(format!("Invalid rotation angle: {}", degrees)));
+    }
+
+    let degrees = degrees.rem_euclid(360.0);
+    let quarter_turns = (degrees / 90.0).round();
+    if (degrees - quarter_turns * 90.0).abs() < 1e-4 {
+        let quarter_turns = quarter_turns as u32 % 4;
+        // Keeping a non-square canvas at 90 or 270 degrees clips, which needs the general path
+        if quarter_turns.is_multiple_of(2) || options.expand || img.width() == img.height() {
+            return Ok(match quarter_turns {
+                0 => img.clone(),
+                1 => img.rotate90(),
+                2 => img.rotate180(),
+                _ => img.rotate270(),
+            });
+        }
+    }
+    Ok(rotate_resampled(img, degrees.to_radians(), options))
+}
+
+fn rotate_resampled(img: &DynamicImage, radians: f32, options: &RotateOptions) -> DynamicImage {
+    let (sin, cos) = radians.sin_cos();
+    let (width, height) = (img.width() as f32, img.height() as f32);
+    let (out_width, out_height) = if options.expand {
+        // The epsilon keeps float noise from adding a row of background
+        let fit = |extent: f32| ((extent - 1e-3).ceil() as u32).max(1);
+        (fit(width * cos.abs() + height * sin.abs()), fit(width * sin.abs() + height * cos.abs()))
+    } else {
+        (img.width(), img.height())
+    };
+
+    // Interpolate premultiplied so transparent neighbours do not darken the edges
+    let mut source = img.to_rgba32f();
+    for pixel in source.pixels_mut() {
+        premultiply(&mut pixel.0);
+    }
+    let mut background = options.background.0.map(|c| f32::from(c) / 255.0);
+    premultiply(&mut background);
+    let tap = |x: i64, y: i64| -> [f32; 4] {
+        if x < 0 || y < 0 || x >= i64::from(source.width()) || y >= i64::from(source.height()) {
+            background
+        } else {
+            source.get_pixel(x as u32, y as u32).0
         }
     };
-    rotated_image.save(output_path)?;
-    info!(message = "Image rotated");
-    Ok(())
+
+    let mut rotated = Rgba32FImage::new(out_width, out_height);
+    let (center_x, center_y) = (out_width as f32 / 2.0, out_height as f32 / 2.0);
+    for (x, y, pixel) in rotated.enumerate_pixels_mut() {
+        // Map the output pixel center back into the source
+        let dx = x as f32 + 0.5 - center_x;
+        let dy = y as f32 + 0.5 - center_y;
+        let sx = cos * dx + sin * dy + width / 2.0 - 0.5;
+        let sy = -sin * dx + cos * dy + height / 2.0 - 0.5;
+        let mut rgba = sample(&tap, sx, sy, options.interpolation);
+        let alpha = rgba[3].clamp(0.0, 1.0);
+        for c in &mut rgba[..3] {
+            *c = if alpha > 0.0 { (*c / alpha).clamp(0.0, 1.0) } else { 0.0 };
+        }
+        rgba[3] = alpha;
+        *pixel = Rgba(rgba);
+    }
+
+    let color = if options.background[3] < u8::MAX { with_alpha(img.color()) } else { img.color() };
+    color_space::to_color_type(DynamicImage::ImageRgba32F(rotated), color)
+}
+
+fn premultiply(rgba: &mut [f32; 4]) {
+    let alpha = rgba[3];
+    for c in &mut rgba[..3] {
+        *c *= alpha;
+    }
+}
+
+fn sample(tap: &impl Fn(i64, i64) -> [f32; 4], x: f32, y: f32, interpolation: Interpolation) -> [f32; 4] {
+    match interpolation {
+        Interpolation::Nearest => tap(x.round() as i64, y.round() as i64),
+        Interpolation::Bilinear => {
+            let (x0, y0) = (x.floor(), y.floor());
+            let (fx, fy) = (x - x0, y - y0);
+            let weights = [(0, 0, (1.0 - fx) * (1.0 - fy)), (1, 0, fx * (1.0 - fy)), (0, 1, (1.0 - fx) * fy), (1, 1, fx * fy)];
+            weighted_sum(tap, x0 as i64, y0 as i64, weights)
+        }
+        Interpolation::Bicubic => {
+            let (x0, y0) = (x.floor(), y.floor());
+            let (wx, wy) = (catmull_rom(x - x0), catmull_rom(y - y0));
+            let weights = (0..16).map(|i| (i % 4 - 1, i / 4 - 1, wx[(i % 4) as usize] * wy[(i / 4) as usize]));
+            weighted_sum(tap, x0 as i64, y0 as i64, weights)
+        }
+    }
+}
+
+fn weighted_sum(
+    tap: &impl Fn(i64, i64) -> [f32; 4],
+    x0: i64,
+    y0: i64,
+    weights: impl IntoIterator<Item = (i64, i64, f32)>,
+) -> [f32; 4] {
+    let mut sum = [0.0; 4];
+    for (dx, dy, weight) in weights {
+        let value = tap(x0 + dx, y0 + dy);
+        for (s, v) in sum.iter_mut().zip(value) {
+            *s += v * weight;
+        }
+    }
+    sum
+}
+
+/// Weights of the four taps around a sample at fraction `t` past the second one.
+fn catmull_rom(t: f32) -> [f32; 4] {
+    let (t2, t3) = (t * t, t * t * t);
+    [
+        (-t3 + 2.0 * t2 - t) / 2.0,
+        (3.0 * t3 - 5.0 * t2 + 2.0) / 2.0,
+        (-3.0 * t3 + 4.0 * t2 + t) / 2.0,
+        (t3 - t2) / 2.0,
+    ]
+}
+
+/// Parses `transparent`, `#rrggbb` or `#rrggbbaa`.
+pub fn parse_color(s: &str) -> Result<Rgba<u8>, String> {
+    if s.eq_ignore_ascii_case("transparent") {
+        return Ok(Rgba([0, 0, 0, 0]));
+    }
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    let channel = |i: usize| hex.get(i..i + 2).and_then(|c| u8::from_str_radix(c, 16).ok());
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(r), Some(g), Some(b)) => Ok(Rgba([r, g, b, u8::MAX])),
+        (8, Some(r), Some(g), Some(b)) => {
+            channel(6).map(|a| Rgba([r, g, b, a])).ok_or_else(|| format!("Invalid color `{s}`"))
+        }
+        _ => Err(format!("Invalid color `{s}`, expected `transparent` or `#rrggbb[aa]`")),
+    }
 }
 
+/// What [`flatten`] does with transparent pixels for output formats without an alpha channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlphaPolicy {
+    /// Composite onto a solid color.
+    Flatten(Rgb<u8>),
+    /// Composite onto the gray and white checkerboard image editors show behind transparency.
+    Checkerboard,
+    /// Fail rather than discard transparency.
+    Error,
+}
 
+impl Default for AlphaPolicy {
+    fn default() -> Self {
+        AlphaPolicy::Flatten(Rgb([u8::MAX; 3]))
+    }
+}
 
-#[instrument(level = "info", skip_all, fields(input_path = %input_path.display(), output_path = %output_path.display()))]
-pub fn flip_image(input_path: &Path, output_path: &Path, horizontal: bool, vertical: bool) -> ImageResult<()> {
-    let img = image::open(input_path)?;
+/// Parses `checkerboard`, `error` or an opaque background color like `#ffffff`.
+impl FromStr for AlphaPolicy {
+    type Err = String;
 
-    let flipped_img = if horizontal && vertical {
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "checkerboard" => Ok(AlphaPolicy::Checkerboard),
+            "error" => Ok(AlphaPolicy::Error),
+            _ => match parse_color(s)? {
+                Rgba([r, g, b, u8::MAX]) => Ok(AlphaPolicy::Flatten(Rgb([r, g, b]))),
+                _ => Err(format!("The alpha background `{s}` must be opaque")),
+            },
+        }
+    }
+}
+
+impl fmt::Display for AlphaPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlphaPolicy::Flatten(Rgb([r, g, b])) => write!(f, "#{r:02x}{g:02x}{b:02x}"),
+            AlphaPolicy::Checkerboard => write!(f, "checkerboard"),
+            AlphaPolicy::Error => write!(f, "error"),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for AlphaPolicy {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Side of a checkerboard square in pixels.
+const CHECKER_SIZE: u32 = 8;
+
+/// Whether any pixel of `img` is not fully opaque.
+pub fn has_transparency(img: &DynamicImage) -> bool {
+    img.color().has_alpha() && img.to_rgba32f().pixels().any(|p| p[3] < 1.0)
+}
+
+/// Removes the alpha channel of `img` according to `policy`, keeping the bit depth.
+///
+/// Fully opaque images only lose the channel, [`AlphaPolicy::Error`] fails for the others.
+pub fn flatten(img: &DynamicImage, policy: AlphaPolicy) -> ImageResult<DynamicImage> {
+    let color = without_alpha(img.color());
+    if !has_transparency(img) {
+        return Ok(color_space::to_color_type(img.clone(), color));
+    }
+    let solid = match policy {
+        AlphaPolicy::Flatten(Rgb(rgb)) => Some(rgb.map(|c| f32::from(c) / 255.0)),
+        AlphaPolicy::Checkerboard => None,
+        AlphaPolicy::Error => {
+            return Err(parameter_error("The image has transparent pixels the output format cannot store".to_string()));
+        }
+    };
+    let mut flat = img.to_rgba32f();
+    for (x, y, pixel) in flat.enumerate_pixels_mut() {
+        let alpha = pixel[3];
+        let square = (x / CHECKER_SIZE + y / CHECKER_SIZE) % 2;
+        let background = solid.unwrap_or(if square == 0 { [1.0; 3] } else { [0.8; 3] });
+        for (c, b) in pixel.0[..3].iter_mut().zip(background) {
+            *c = *c * alpha + b * (1.0 - alpha);
+        }
+        pixel[3] = 1.0;
+    }
+    Ok(color_space::to_color_type(DynamicImage::ImageRgba32F(flat), color))
+}
+
+/// The color type without the alpha channel of `color`.
+fn without_alpha(color: ColorType) -> ColorType {
+    match color {
+        ColorType::La8 => ColorType::L8,
+        ColorType::Rgba8 => ColorType::Rgb8,
+        ColorType::La16 => ColorType::L16,
+        ColorType::Rgba16 => ColorType::Rgb16,
+        ColorType::Rgba32F => ColorType::Rgb32F,
+        color => color,
+    }
+}
+
+/// The color type with an alpha channel that holds `color` without loss.
+fn with_alpha(color: ColorType) -> ColorType {
+    match color {
+        ColorType::L8 => ColorType::La8,
+        ColorType::Rgb8 => ColorType::Rgba8,
+        ColorType::L16 => ColorType::La16,
+        ColorType::Rgb16 => ColorType::Rgba16,
+        ColorType::Rgb32F => ColorType::Rgba32F,
+        color => color,
+    }
+}
+
+/// Mirrors `img` left to right and/or top to bottom.
+pub fn flip(img: DynamicImage, horizontal: bool, vertical: bool) -> DynamicImage {
+    if horizontal && vertical {
         img.flipv().fliph() // Chain flips for both directions
     } else if horizontal {
         img.fliph()
@@ -63,61 +521,582 @@ pub fn flip_image(input_path: &Path, output_path: &Path, horizontal: bool, verti
         img.flipv()
     } else {
         img
+    }
+}
+
+
+/// The `width` x `height` rectangle at `x`, `y`, failing when it is empty or does not fit inside
+/// the image.
+pub fn crop(img: &DynamicImage, x: u32, y: u32, width: u32, height: u32) -> ImageResult<DynamicImage> {
+    let (img_width, img_height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return Err(parameter_error(format!("Crop size {width}x{height} is empty")));
+    }
+    if u64::from(x) + u64::from(width) > u64::from(img_width) ||
+        u64::from(y) + u64::from(height) > u64::from(img_height)
+    {
+        return Err(
+            parameter_error(
+                format!("Crop {width}x{height} at {x},{y} exceeds the {img_width}x{img_height} image")
+            )
+        );
+    }
+    Ok(img.crop_imm(x, y, width, height)) // Keeps the source color type
+}
+
+/// Width to height ratio of a crop, written `16:9`, `1:1` or `4:5`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AspectRatio {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl AspectRatio {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self { width, height }
+    }
+}
+
+impl FromStr for AspectRatio {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parsed = s
+            .split_once(':')
+            .and_then(|(width, height)| Some((width.trim().parse().ok()?, height.trim().parse().ok()?)));
+        match parsed {
+            Some((width, height)) if width > 0 && height > 0 => Ok(Self { width, height }),
+            _ => Err(format!("Invalid aspect ratio `{s}`, expected e.g. `16:9`")),
+        }
+    }
+}
+
+impl fmt::Display for AspectRatio {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.width, self.height)
+    }
+}
+
+/// Which part of the image [`smart_crop`] keeps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Gravity {
+    #[default]
+    Center,
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    South,
+    SouthWest,
+    West,
+    NorthWest,
+    /// The region with the most edge energy, usually the subject of a photo.
+    Attention,
+}
+
+/// Crops `img` to the largest `aspect` rectangle placed by `gravity`, see [`smart_crop_rect`].
+pub fn smart_crop(img: &DynamicImage, aspect: AspectRatio, gravity: Gravity) -> ImageResult<DynamicImage> {
+    let (x, y, width, height) = smart_crop_rect(img, aspect, gravity)?;
+    crop(img, x, y, width, height)
+}
+
+/// The `(x, y, width, height)` of the largest `aspect` rectangle inside `img`, placed by `gravity`.
+pub fn smart_crop_rect(
+    img: &DynamicImage,
+    aspect: AspectRatio,
+    gravity: Gravity
+) -> ImageResult<(u32, u32, u32, u32)> {
+    let (width, height) = img.dimensions();
+    if aspect.width == 0 || aspect.height == 0 {
+        return Err(parameter_error(format!("Invalid aspect ratio {aspect}")));
+    }
+    if width == 0 || height == 0 {
+        return Err(parameter_error("Cannot crop an empty image".to_string()));
+    }
+    let (aspect_width, aspect_height) = (u64::from(aspect.width), u64::from(aspect.height));
+    let (crop_width, crop_height) = if u64::from(width) * aspect_height >= u64::from(height) * aspect_width {
+        let crop_width = (u64::from(height) * aspect_width + aspect_height / 2) / aspect_height;
+        (crop_width.clamp(1, u64::from(width)) as u32, height)
+    } else {
+        let crop_height = (u64::from(width) * aspect_height + aspect_width / 2) / aspect_width;
+        (width, crop_height.clamp(1, u64::from(height)) as u32)
+    };
+    let (free_x, free_y) = (width - crop_width, height - crop_height);
+
+    let (x, y) = match gravity {
+        Gravity::Attention => attention_offset(img, crop_width, crop_height),
+        _ => {
+            // In halves of the free space: 0 hugs the left or top edge, 2 the right or bottom edge
+            let (column, row) = match gravity {
+                Gravity::NorthWest => (0, 0),
+                Gravity::North => (1, 0),
+                Gravity::NorthEast => (2, 0),
+                Gravity::West => (0, 1),
+                Gravity::East => (2, 1),
+                Gravity::SouthWest => (0, 2),
+                Gravity::South => (1, 2),
+                Gravity::SouthEast => (2, 2),
+                Gravity::Center | Gravity::Attention => (1, 1),
+            };
+            ((u64::from(free_x) * column / 2) as u32, (u64::from(free_y) * row / 2) as u32)
+        }
     };
+    Ok((x, y, crop_width, crop_height))
+}
 
-    flipped_img.save(output_path)?;
-    info!(message = "Image flipped");
-    Ok(())
-}
-
-
-#[instrument(level = "info", skip_all, fields(input_path = %input_path.display(), output_path = %output_path.display(), sigma = sigma))]
-pub fn blur_image(input_path: &Path, output_path: &Path, sigma: f32) -> ImageResult<()> {
-    let img = image::open(input_path)?;
-    let blurred_img = img.blur(sigma);
-    blurred_img.save(output_path)?;
-    info!(message = "Image blurred");
-
-    Ok(())
-}
-
-#[instrument(level = "info", skip_all, fields(input_path = %input_path.display(), output_path = %output_path.display(), x = x, y = y, width = width, height = height))]
-pub fn crop_image(
-    input_path: &Path,
-    output_path: &Path,
-    x: u32,
-    y: u32,
-    width: u32,
-    height: u32,
-) -> ImageResult<()> {
-    let mut img = image::open(input_path)?;
-    let cropped_img = imageops::crop(&mut img, x, y, width, height).to_image(); // Extract the cropped image
-    cropped_img.save(output_path)?;
-    info!(message = "Image cropped");
-
-    Ok(())
-}
-
-#[instrument(level = "info", skip_all, fields(input_path = %input_path.display(), output_path = %output_path.display(), sigma = sigma, threshold = threshold))]
-pub fn unsharpen_image(
-    input_path: &Path,
-    output_path: &Path,
-    sigma: f32,
-    threshold: i32,
-) -> ImageResult<()> {
-    let img = image::open(input_path)?;
-    let unsharpened_img = img.unsharpen(sigma, threshold);
-    unsharpened_img.save(output_path)?;
-    info!(message = "Image unsharpened");
-    Ok(())
-}
-
-#[instrument(level = "info", skip_all, fields(input_path = %input_path.display(), output_path = %output_path.display(), value = value))]
-pub fn brighten_image(input_path: &Path, output_path: &Path, value: i32) -> ImageResult<()> {
-    let img = image::open(input_path)?;
-    let brightened_img = img.brighten(value);
-    brightened_img.save(output_path)?;
-    info!(message = "Image brightened");
-
-    Ok(())
-}
\ No newline at end of file
+/// Offset of the `crop_width` x `crop_height` window holding the most edge energy.
+///
+/// The window always spans the image along one axis, so only the sums along the other axis
+/// matter. An image without edges is cropped from the center.
+fn attention_offset(img: &DynamicImage, crop_width: u32, crop_height: u32) -> (u32, u32) {
+    let (width, height) = img.dimensions();
+    let (free_x, free_y) = (width - crop_width, height - crop_height);
+    if free_x == 0 && free_y == 0 {
+        return (0, 0);
+    }
+    // A 256 pixel thumbnail is plenty to find where the detail is
+    let thumbnail = if width.max(height) > 256 {
+        img.resize(256, 256, FilterType::Triangle).to_luma8()
+    } else {
+        img.to_luma8()
+    };
+    let (thumb_width, thumb_height) = thumbnail.dimensions();
+    let luma = |x: u32, y: u32| f32::from(thumbnail.get_pixel(x.min(thumb_width - 1), y.min(thumb_height - 1))[0]);
+    let energy = |x: u32, y: u32| (luma(x + 1, y) - luma(x, y)).abs() + (luma(x, y + 1) - luma(x, y)).abs();
+
+    let (lines, length, free, window) = if free_x > 0 {
+        let columns: Vec<f32> = (0..thumb_width)
+            .map(|x| (0..thumb_height).map(|y| energy(x, y)).sum())
+            .collect();
+        (columns, width, free_x, crop_width)
+    } else {
+        let rows: Vec<f32> = (0..thumb_height)
+            .map(|y| (0..thumb_width).map(|x| energy(x, y)).sum())
+            .collect();
+        (rows, height, free_y, crop_height)
+    };
+    let scale = lines.len() as f64 / f64::from(length);
+    let window = ((f64::from(window) * scale).round() as usize).clamp(1, lines.len());
+    let center = (lines.len() - window) as f64 / 2.0;
+    let best = (0..=lines.len() - window)
+        .map(|start| (start, lines[start..start + window].iter().sum::<f32>()))
+        // Ties, such as a flat image, go to the most central window
+        .max_by(|a, b| {
+            a.1.total_cmp(&b.1).then((b.0 as f64 - center).abs().total_cmp(&(a.0 as f64 - center).abs()))
+        })
+        .map_or(0, |(start, _)| start);
+    let offset = ((best as f64 / scale).round() as u32).min(free);
+    if free_x > 0 { (offset, 0) } else { (0, offset) }
+}
+
+/// Convolves the color channels with a 3x3 `kernel`, see [`KernelPreset`] for common ones.
+///
+/// Unlike `DynamicImage::filter3x3`, edge pixels are filtered against their clamped neighbours and
+/// alpha is left untouched. The kernel is normalized by its sum unless that sum is zero.
+pub fn filter3x3(img: &DynamicImage, kernel: &[f32; 9]) -> DynamicImage {
+    let source = img.to_rgba32f();
+    let (width, height) = source.dimensions();
+    let scale = match kernel.iter().sum::<f32>() {
+        0.0 => 1.0,
+        sum => 1.0 / sum,
+    };
+
+    let mut filtered = source.clone();
+    for (x, y, pixel) in filtered.enumerate_pixels_mut() {
+        let mut sum = [0.0f32; 3];
+        for (i, weight) in kernel.iter().enumerate() {
+            let sx = (x + i as u32 % 3).saturating_sub(1).min(width - 1);
+            let sy = (y + i as u32 / 3).saturating_sub(1).min(height - 1);
+            let tap = source.get_pixel(sx, sy);
+            for (c, channel) in sum.iter_mut().enumerate() {
+                *channel += tap[c] * weight;
+            }
+        }
+        for (c, channel) in sum.into_iter().enumerate() {
+            pixel[c] = (channel * scale).clamp(0.0, 1.0);
+        }
+    }
+    color_space::to_color_type(DynamicImage::ImageRgba32F(filtered), img.color())
+}
+
+/// Crops away the uniform border around the content of `img`, see [`trim_bounds`].
+pub fn trim(img: &DynamicImage, tolerance: f32) -> DynamicImage {
+    match trim_bounds(img, tolerance) {
+        Some((x, y, width, height)) => img.crop_imm(x, y, width, height),
+        None => img.clone(),
+    }
+}
+
+/// The `(x, y, width, height)` left after removing borders that match the dominant corner color.
+///
+/// `tolerance` is a CIE76 color difference, around 2 is just noticeable; JPEG noise on a scanned
+/// page usually needs 5 to 15. Returns `None` when the image is uniform or has no border.
+pub fn trim_bounds(img: &DynamicImage, tolerance: f32) -> Option<(u32, u32, u32, u32)> {
+    let rgba = img.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let border = border_color(img);
+    let border_lab = Lab::from_rgb(rgb_f32(border));
+    let matches = |x: u32, y: u32| {
+        let pixel = *rgba.get_pixel(x, y);
+        if pixel[3].abs_diff(border[3]) > 25 {
+            return false;
+        }
+        // Fully transparent pixels match whatever color they carry
+        border[3] == 0 || delta_e(Lab::from_rgb(rgb_f32(pixel)), border_lab) <= tolerance
+    };
+    let row_matches = |y: u32| (0..width).all(|x| matches(x, y));
+    let column_matches = |x: u32, top: u32, bottom: u32| (top..bottom).all(|y| matches(x, y));
+
+    let top = (0..height).find(|&y| !row_matches(y))?;
+    let bottom = (top..height).rev().find(|&y| !row_matches(y))? + 1;
+    let left = (0..width).find(|&x| !column_matches(x, top, bottom))?;
+    let right = (left..width).rev().find(|&x| !column_matches(x, top, bottom))? + 1;
+    if (left, top, right, bottom) == (0, 0, width, height) {
+        return None;
+    }
+    Some((left, top, right - left, bottom - top))
+}
+
+/// The corner color shared by most corners, a scan's paper or a logo's padding.
+pub fn border_color(img: &DynamicImage) -> Rgba<u8> {
+    let (right, bottom) = (img.width().saturating_sub(1), img.height().saturating_sub(1));
+    let corners = [(0, 0), (right, 0), (0, bottom), (right, bottom)].map(|(x, y)| img.get_pixel(x, y));
+    let close = |a: &Rgba<u8>, b: &Rgba<u8>| a.0.iter().zip(b.0).all(|(a, b)| a.abs_diff(b) <= 8);
+    corners
+        .iter()
+        .max_by_key(|corner| corners.iter().filter(|other| close(corner, other)).count())
+        .copied()
+        .unwrap_or(Rgba([255, 255, 255, 255]))
+}
+
+fn rgb_f32(pixel: Rgba<u8>) -> [f32; 3] {
+    [pixel[0], pixel[1], pixel[2]].map(|c| f32::from(c) / 255.0)
+}
+
+fn delta_e(a: Lab, b: Lab) -> f32 {
+    ((a.l - b.l).powi(2) + (a.a - b.a).powi(2) + (a.b - b.b).powi(2)).sqrt()
+}
+
+/// Rotates `img` upright by the tilt [`estimate_skew`] finds.
+///
+/// The canvas keeps its size and uncovered corners are filled with the border color.
+pub fn deskew(img: &DynamicImage, max_degrees: f32) -> ImageResult<DynamicImage> {
+    let angle = estimate_skew(img, max_degrees);
+    if angle == 0.0 {
+        return Ok(img.clone());
+    }
+    info!(message = "Correcting skew", angle = angle);
+    let options = RotateOptions {
+        interpolation: Interpolation::Bicubic,
+        expand: false,
+        background: border_color(img),
+    };
+    rotate(img, angle, &options)
+}
+
+/// Clockwise angle, within `max_degrees` either way, that levels the dominant lines of text or
+/// edges.
+///
+/// Ink pixels are rotated by each candidate angle and projected onto the vertical axis; the angle
+/// whose rows are the most concentrated wins. A coarse half degree search is refined to 0.05
+/// degrees.
+pub fn estimate_skew(img: &DynamicImage, max_degrees: f32) -> f32 {
+    // Work on a thumbnail, a 1000 pixel page still resolves tenths of a degree
+    let gray = if img.width().max(img.height()) > 1000 {
+        img.resize(1000, 1000, FilterType::Triangle).to_luma8()
+    } else {
+        img.to_luma8()
+    };
+    let threshold = otsu_threshold(&gray);
+    let dark: Vec<(f32, f32)> = gray
+        .enumerate_pixels()
+        .filter(|(_, _, pixel)| pixel[0] <= threshold)
+        .map(|(x, y, _)| (x as f32, y as f32))
+        .collect();
+    // Ink is whichever side of the threshold is the minority
+    let ink = if dark.len() * 2 <= gray.len() {
+        dark
+    } else {
+        gray.enumerate_pixels()
+            .filter(|(_, _, pixel)| pixel[0] > threshold)
+            .map(|(x, y, _)| (x as f32, y as f32))
+            .collect()
+    };
+    if ink.is_empty() || max_degrees <= 0.0 {
+        return 0.0;
+    }
+
+    let (center_x, center_y) = (gray.width() as f32 / 2.0, gray.height() as f32 / 2.0);
+    let diagonal = (center_x.hypot(center_y).ceil() as usize) * 2 + 2;
+    let score = |degrees: f32| -> f64 {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let mut rows = vec![0u32; diagonal];
+        for (x, y) in &ink {
+            let row = sin * (x - center_x) + cos * (y - center_y) + diagonal as f32 / 2.0;
+            rows[row as usize] += 1;
+        }
+        rows.iter().map(|&count| f64::from(count).powi(2)).sum()
+    };
+    let best = |from: f32, to: f32, step: f32| -> f32 {
+        let steps = ((to - from) / step).round() as i32;
+        (0..=steps)
+            .map(|i| from + i as f32 * step)
+            .map(|degrees| (degrees, score(degrees)))
+            .fold((0.0, f64::MIN), |best, candidate| if candidate.1 > best.1 { candidate } else { best })
+            .0
+    };
+
+    let coarse = best(-max_degrees, max_degrees, 0.5);
+    let fine = best((coarse - 0.5).max(-max_degrees), (coarse + 0.5).min(max_degrees), 0.05);
+    // Rounding keeps a straight page from being resampled over float noise
+    let fine = (fine * 100.0).round() / 100.0;
+    if fine.abs() < 0.05 { 0.0 } else { fine }
+}
+
+/// Threshold that best separates the luma histogram into two classes.
+fn otsu_threshold(gray: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for pixel in gray.pixels() {
+        histogram[usize::from(pixel[0])] += 1;
+    }
+    let total = gray.len() as f64;
+    let sum: f64 = histogram.iter().enumerate().map(|(i, &count)| i as f64 * count as f64).sum();
+    let (mut background, mut background_sum) = (0.0, 0.0);
+    let (mut best, mut best_variance) = (0u8, 0.0);
+    for (level, &count) in histogram.iter().enumerate() {
+        background += count as f64;
+        background_sum += level as f64 * count as f64;
+        let foreground = total - background;
+        if background == 0.0 || foreground == 0.0 {
+            continue;
+        }
+        let mean_difference = background_sum / background - (sum - background_sum) / foreground;
+        let variance = background * foreground * mean_difference * mean_difference;
+        if variance > best_variance {
+            (best, best_variance) = (level as u8, variance);
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ RgbImage, RgbaImage };
+
+    #[test]
+    fn test_kernel_presets() {
+        assert_eq!(KernelPreset::from_kernel(&KernelPreset::Emboss.kernel()), Some(KernelPreset::Emboss));
+        assert_eq!(KernelPreset::from_kernel(&[0.0; 9]), None);
+    }
+
+    #[test]
+    fn test_filter3x3_keeps_edges_and_alpha() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, Rgba([100, 150, 200, 128])));
+        for preset in [KernelPreset::Sharpen, KernelPreset::BoxBlur, KernelPreset::Emboss] {
+            let filtered = filter3x3(&img, &preset.kernel());
+            assert_eq!(filtered.color(), img.color());
+            // A flat image stays flat, including its border, under kernels that sum to one
+            assert_eq!(filtered.to_rgba8().get_pixel(0, 0), &Rgba([100, 150, 200, 128]), "{preset:?}");
+        }
+        let edges = filter3x3(&img, &KernelPreset::EdgeDetect.kernel()).to_rgba8();
+        assert_eq!(edges.get_pixel(3, 3), &Rgba([0, 0, 0, 128]));
+    }
+
+    #[test]
+    fn test_rotate_right_angles_are_exact() {
+        let img = DynamicImage::new_rgb8(30, 10);
+        let options = RotateOptions::default();
+        assert_eq!(rotate(&img, 90.0, &options).unwrap().dimensions(), (10, 30));
+        assert_eq!(rotate(&img, -90.0, &options).unwrap().dimensions(), (10, 30));
+        assert_eq!(rotate(&img, 360.0, &options).unwrap(), img);
+        assert!(rotate(&img, f32::NAN, &options).is_err());
+
+        let keep = RotateOptions { expand: false, ..options };
+        assert_eq!(rotate(&img, 90.0, &keep).unwrap().dimensions(), (30, 10));
+    }
+
+    #[test]
+    fn test_rotate_arbitrary_angle_fills_background() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(30, 10, image::Rgb([0, 0, 255])));
+        let transparent = rotate(&img, 45.0, &RotateOptions::default()).unwrap();
+        assert_eq!(transparent.dimensions(), (29, 29));
+        assert_eq!(transparent.color(), ColorType::Rgba8);
+        assert_eq!(transparent.to_rgba8().get_pixel(0, 0)[3], 0);
+        assert_eq!(transparent.to_rgba8().get_pixel(14, 14), &Rgba([0, 0, 255, 255]));
+
+        for interpolation in [Interpolation::Nearest, Interpolation::Bilinear, Interpolation::Bicubic] {
+            let options = RotateOptions { interpolation, expand: false, background: Rgba([255, 0, 0, 255]) };
+            let rotated = rotate(&img, -10.0, &options).unwrap();
+            assert_eq!(rotated.dimensions(), (30, 10));
+            assert_eq!(rotated.color(), ColorType::Rgb8);
+            assert_eq!(rotated.to_rgb8().get_pixel(15, 5), &image::Rgb([0, 0, 255]), "{interpolation:?}");
+            assert_eq!(rotated.to_rgb8().get_pixel(0, 0), &image::Rgb([255, 0, 0]), "{interpolation:?}");
+        }
+    }
+
+    /// White page with dark horizontal bars standing in for lines of text.
+    fn page() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(240, 160, |x, y| {
+            let text = (30..210).contains(&x) && (20..140).contains(&y) && y % 16 < 4;
+            if text { image::Rgb([20, 20, 20]) } else { image::Rgb([250, 250, 250]) }
+        }))
+    }
+
+    #[test]
+    fn test_trim_bounds_with_tolerance() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(80, 60, |x, y| {
+            if (10..40).contains(&x) && (20..50).contains(&y) {
+                image::Rgb([200, 30, 30])
+            } else {
+                // Noise well within the tolerance
+                image::Rgb([250 - (x % 3) as u8, 250, 250 - (y % 2) as u8])
+            }
+        }));
+        assert_eq!(trim_bounds(&img, 5.0), Some((10, 20, 30, 30)));
+        assert_eq!(trim(&img, 5.0).dimensions(), (30, 30));
+        assert_eq!(trim_bounds(&img, 200.0), None);
+        assert_eq!(trim_bounds(&DynamicImage::new_rgba8(8, 8), 0.0), None);
+    }
+
+    #[test]
+    fn test_estimate_skew() {
+        let straight = page();
+        assert_eq!(estimate_skew(&straight, 10.0), 0.0);
+
+        let options = RotateOptions { expand: false, background: Rgba([250, 250, 250, 255]), ..RotateOptions::default() };
+        let skewed = rotate(&straight, 3.0, &options).unwrap();
+        let angle = estimate_skew(&skewed, 10.0);
+        assert!((angle + 3.0).abs() <= 0.2, "{angle}");
+        assert_eq!(deskew(&skewed, 10.0).unwrap().dimensions(), straight.dimensions());
+    }
+
+    #[test]
+    fn test_crop_validates_bounds() {
+        let img = DynamicImage::new_rgb8(40, 30);
+        assert_eq!(crop(&img, 10, 10, 30, 20).unwrap().dimensions(), (30, 20));
+        assert!(crop(&img, 10, 10, 31, 20).is_err());
+        assert!(crop(&img, 0, 0, 0, 10).is_err());
+        assert!(crop(&img, u32::MAX, 0, 2, 2).is_err());
+    }
+
+    #[test]
+    fn test_smart_crop_gravity() {
+        let img = DynamicImage::new_rgb8(200, 100);
+        let square = AspectRatio::new(1, 1);
+        assert_eq!(smart_crop_rect(&img, square, Gravity::Center).unwrap(), (50, 0, 100, 100));
+        assert_eq!(smart_crop_rect(&img, square, Gravity::NorthWest).unwrap(), (0, 0, 100, 100));
+        assert_eq!(smart_crop_rect(&img, square, Gravity::SouthEast).unwrap(), (100, 0, 100, 100));
+        // Flat images have no detail to follow and crop from the center
+        assert_eq!(smart_crop_rect(&img, square, Gravity::Attention).unwrap(), (50, 0, 100, 100));
+
+        let portrait = AspectRatio::new(4, 5);
+        assert_eq!(smart_crop_rect(&img, portrait, Gravity::North).unwrap(), (60, 0, 80, 100));
+        let wide: AspectRatio = "16:9".parse().unwrap();
+        assert_eq!(smart_crop_rect(&img, wide, Gravity::East).unwrap(), (22, 0, 178, 100));
+        let panorama: AspectRatio = "3:1".parse().unwrap();
+        assert_eq!(smart_crop_rect(&img, panorama, Gravity::South).unwrap(), (0, 33, 200, 67));
+        assert!("16:0".parse::<AspectRatio>().is_err());
+        assert!(smart_crop_rect(&img, AspectRatio::new(0, 1), Gravity::Center).is_err());
+    }
+
+    #[test]
+    fn test_smart_crop_attention_finds_detail() {
+        // A checkered patch near the right edge of an otherwise flat image
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(300, 100, |x, y| {
+            if (230..280).contains(&x) && (x / 5 + y / 5) % 2 == 0 { image::Rgb([0, 0, 0]) } else { image::Rgb([255, 255, 255]) }
+        }));
+        let (x, y, width, height) = smart_crop_rect(&img, AspectRatio::new(1, 1), Gravity::Attention).unwrap();
+        assert_eq!((y, width, height), (0, 100, 100));
+        assert!(x <= 230 && x + width >= 280, "{x}");
+    }
+
+    #[test]
+    fn test_resize_modes() {
+        let img = DynamicImage::new_rgb8(200, 100);
+        let resized = |size, mode| resize(&img, &ResizeOptions::new(size, mode)).unwrap();
+        let square = ResizeSize::Pixels { width: 100, height: 100 };
+        assert_eq!(resized(square, ResizeMode::Fit).dimensions(), (100, 50));
+        assert_eq!(resized(square, ResizeMode::Fill).dimensions(), (100, 100));
+        assert_eq!(resized(square, ResizeMode::Cover).dimensions(), (100, 100));
+        assert_eq!(resized(ResizeSize::Pixels { width: 50, height: 0 }, ResizeMode::Fill).dimensions(), (50, 25));
+
+        let contained = resized(square, ResizeMode::Contain);
+        assert_eq!(contained.dimensions(), (100, 100));
+        assert_eq!(contained.color(), ColorType::Rgba8);
+        assert_eq!(contained.get_pixel(50, 10)[3], 0);
+        assert_eq!(contained.get_pixel(50, 50), Rgba([0, 0, 0, 255]));
+
+        let large = ResizeSize::Pixels { width: 400, height: 400 };
+        assert_eq!(resized(large, ResizeMode::Fit).dimensions(), (400, 200));
+        assert_eq!(resized(large, ResizeMode::Shrink).dimensions(), (200, 100));
+        assert_eq!(resized(ResizeSize::Percent(50.0), ResizeMode::Fit).dimensions(), (100, 50));
+        assert_eq!(resized(ResizeSize::Megapixels(0.005), ResizeMode::Fit).dimensions(), (100, 50));
+    }
+
+    #[test]
+    fn test_resize_filter() {
+        // Two-color stripes stay two-colored with nearest neighbour only
+        let stripes = DynamicImage::ImageLuma8(
+            image::GrayImage::from_fn(8, 8, |x, _| image::Luma([if x % 2 == 0 { 0 } else { 255 }]))
+        );
+        let mut options = ResizeOptions::new(ResizeSize::Percent(300.0), ResizeMode::Fit);
+        options.filter = ResizeFilter::Nearest;
+        let nearest = resize(&stripes, &options).unwrap().to_luma8();
+        assert!(nearest.pixels().all(|p| p[0] == 0 || p[0] == 255));
+        options.filter = ResizeFilter::Lanczos3;
+        let lanczos = resize(&stripes, &options).unwrap().to_luma8();
+        assert!(lanczos.pixels().any(|p| p[0] != 0 && p[0] != 255));
+        assert_eq!(FilterType::from(ResizeFilter::CatmullRom), FilterType::CatmullRom);
+    }
+
+    #[test]
+    fn test_resize_rejects_invalid_sizes() {
+        let img = DynamicImage::new_rgb8(200, 100);
+        for size in [
+            ResizeSize::Pixels { width: 0, height: 0 },
+            ResizeSize::Pixels { width: MAX_DIMENSION + 1, height: 10 },
+            ResizeSize::Percent(0.0),
+            ResizeSize::Percent(f32::NAN),
+            ResizeSize::Megapixels(-1.0),
+            ResizeSize::Megapixels(f32::INFINITY),
+        ] {
+            assert!(resize(&img, &ResizeOptions::new(size, ResizeMode::Fit)).is_err(), "{size}");
+        }
+        let empty = DynamicImage::new_rgb8(0, 0);
+        assert!(resize(&empty, &ResizeOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_flatten_alpha() {
+        // Transparent pixels hide green, which must not show through
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(16, 16, |x, _| {
+            if x < 8 { Rgba([0, 255, 0, 0]) } else { Rgba([255, 0, 0, 255]) }
+        }));
+        let white = flatten(&img, AlphaPolicy::default()).unwrap();
+        assert_eq!(white.color(), ColorType::Rgb8);
+        assert_eq!(white.to_rgb8().get_pixel(0, 0), &Rgb([255, 255, 255]));
+        assert_eq!(white.to_rgb8().get_pixel(12, 0), &Rgb([255, 0, 0]));
+
+        let checkers = flatten(&img, AlphaPolicy::Checkerboard).unwrap().to_rgb8();
+        assert_ne!(checkers.get_pixel(0, 0), checkers.get_pixel(0, CHECKER_SIZE));
+        assert!(flatten(&img, AlphaPolicy::Error).is_err());
+
+        // Opaque images only lose the channel, whatever the policy
+        let opaque = image::ImageBuffer::from_pixel(4, 4, Rgba([1u16, 2, 3, u16::MAX]));
+        let flat = flatten(&DynamicImage::ImageRgba16(opaque), AlphaPolicy::Error).unwrap();
+        assert_eq!(flat.color(), ColorType::Rgb16);
+    }
+
+    #[test]
+    fn test_parse_alpha_policy() {
+        assert_eq!("#000080".parse(), Ok(AlphaPolicy::Flatten(Rgb([0, 0, 128]))));
+        assert_eq!("Checkerboard".parse(), Ok(AlphaPolicy::Checkerboard));
+        assert_eq!("error".parse(), Ok(AlphaPolicy::Error));
+        assert!("transparent".parse::<AlphaPolicy>().is_err());
+        assert!("#12345".parse::<AlphaPolicy>().is_err());
+    }
+}
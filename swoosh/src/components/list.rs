@@ -14,7 +14,7 @@ use tokio::sync::mpsc::UnboundedSender;
 use crate::{
     action::Action,
     components::{ value::{ ImageInfo, ImageStatus }, Component },
-    config::{ key_event_to_string, parse_key_sequence, Config },
+    config::Config,
     tui::Event,
 };
 
@@ -53,6 +53,13 @@ impl ImageList {
         self.last_selection = Some(new_index);
     }
 
+    /// Indices of the selected rows in list order.
+    pub fn selected(&self) -> Vec<usize> {
+        let mut selected: Vec<usize> = self.selected_indices.iter().copied().collect();
+        selected.sort_unstable();
+        selected
+    }
+
     pub fn remove_image(&mut self, index: usize) {
         if index < self.image_data.len() {
             self.image_data.remove(index);
@@ -77,12 +84,29 @@ impl ImageList {
         self.table_state.select(None); // Clear selection after removal
     }
 
-    fn update_image_status(&mut self, index: usize, status: ImageStatus) {
-        if let Some(image) = self.image_data.get_mut(index) {
+    /// The row with `id`, `None` once it was removed.
+    fn image_mut(&mut self, id: usize) -> Option<&mut ImageInfo> {
+        self.image_data.iter_mut().find(|image| image.id == id)
+    }
+
+    fn update_image_status(&mut self, id: usize, status: ImageStatus) {
+        if let Some(image) = self.image_mut(id) {
             image.status = status;
         }
     }
 
+    fn update_image_details(&mut self, id: usize, details: String) {
+        if let Some(image) = self.image_mut(id) {
+            image.details = Some(details);
+        }
+    }
+
+    fn update_image_warning(&mut self, id: usize, warning: String) {
+        if let Some(image) = self.image_mut(id) {
+            image.warning = Some(warning);
+        }
+    }
+
     fn clear_images(&mut self) {
         self.image_data.clear();
         self.table_state.select(None);
@@ -200,8 +224,14 @@ impl Component for ImageList {
     fn update(&mut self, action: Action) -> Result<Option<Action>> {
         match action {
             Action::ClearImages => self.clear_images(),
-            Action::UpdateImageStatus { index, status } => {
-                self.update_image_status(index, status);
+            Action::UpdateImageStatus { id, status } => {
+                self.update_image_status(id, status);
+            }
+            Action::UpdateImageDetails { id, details } => {
+                self.update_image_details(id, details);
+            }
+            Action::UpdateImageWarning { id, warning } => {
+                self.update_image_warning(id, warning);
             }
             _ => {}
         }
@@ -218,6 +248,7 @@ impl Component for ImageList {
                 let status_style = match item.status {
                     ImageStatus::Queued => Style::default().fg(Color::Gray),
                     ImageStatus::Converting => Style::default().fg(Color::Yellow),
+                    ImageStatus::Completed if item.warning.is_some() => Style::default().fg(Color::LightYellow),
                     ImageStatus::Completed => Style::default().fg(Color::Green),
                     ImageStatus::Error(_) => Style::default().fg(Color::Red),
                 };
@@ -232,7 +263,7 @@ impl Component for ImageList {
                     vec![
                         Cell::from(item.filename.clone()),
                         Cell::from(item.size.clone()),
-                        Cell::from(Span::styled(item.status.to_string(), status_style))
+                        Cell::from(Span::styled(item.status_text(), status_style))
                     ]
                 ).style(row_style)
             });
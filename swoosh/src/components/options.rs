@@ -1,23 +1,287 @@
-﻿use color_eyre::Result;
+﻿use clap::ValueEnum;
+use color_eyre::Result;
+use image::{ codecs::avif::ColorSpace, ImageFormat };
 use ratatui::{
     layout::{ Constraint, Direction, Layout, Rect },
-    style::{ Color, Modifier, Style },
-    text::Span,
-    widgets::{ Block, Borders, Clear },
+    style::{ Modifier, Style },
+    text::{ Line, Span },
+    widgets::{ Block, Borders, Clear, Paragraph },
     Frame,
 };
 
-use crate::{ action::Action, components::Component, config::Config, tui::Event };
+use crate::{
+    action::Action,
+    components::{
+        image_processing::{
+            avif::ChromaSubsampling,
+            compress::EncodeOptions,
+            formats::{ IcoSizes, PnmKind, TiffCompression },
+            metadata::MetadataPolicy,
+            quantize::Dither,
+        },
+        Component,
+    },
+    config::Config,
+    tui::Event,
+};
+
+/// Output formats the panel cycles through.
+const FORMATS: [ImageFormat; 13] = [
+    ImageFormat::Png,
+    ImageFormat::Jpeg,
+    ImageFormat::WebP,
+    ImageFormat::Avif,
+    ImageFormat::Gif,
+    ImageFormat::OpenExr,
+    ImageFormat::Tiff,
+    ImageFormat::Bmp,
+    ImageFormat::Ico,
+    ImageFormat::Qoi,
+    ImageFormat::Pnm,
+    ImageFormat::Tga,
+    ImageFormat::Farbfeld,
+];
+
+/// Byte budgets the target size row cycles through, `None` turns the search off.
+const TARGET_SIZES: [Option<u64>; 7] = [
+    None,
+    Some(50_000),
+    Some(100_000),
+    Some(200_000),
+    Some(500_000),
+    Some(1_000_000),
+    Some(2_000_000),
+];
+
+/// ICO size sets the sizes row cycles through: favicon, favicon with a 256 pixel frame, all.
+const ICO_SIZE_SETS: [IcoSizes; 3] = [
+    IcoSizes::FAVICON,
+    IcoSizes::FAVICON.union(IcoSizes::S256),
+    IcoSizes::all(),
+];
+
+/// GIF palette sizes the colors row cycles through.
+const GIF_COLORS: [u16; 8] = [256, 128, 64, 32, 16, 8, 4, 2];
+
+/// GIF quantizer speeds the speed row cycles through, slowest first.
+const GIF_SPEEDS: [u8; 7] = [1, 5, 10, 15, 20, 25, 30];
+
+/// GIF alpha thresholds the alpha threshold row cycles through, 0 keeps every pixel opaque.
+const GIF_ALPHA_THRESHOLDS: [u8; 5] = [0, 64, 128, 192, 255];
+
+/// Metadata policies the metadata row cycles through.
+const METADATA_POLICIES: [MetadataPolicy; 4] = [
+    MetadataPolicy::KeepAll,
+    MetadataPolicy::StripGps,
+    MetadataPolicy::CopyrightIcc,
+    MetadataPolicy::StripAll,
+];
+
+/// One editable line of the panel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OptionRow {
+    Format,
+    Quality,
+    TargetSize,
+    WebpLossless,
+    AvifSpeed,
+    AvifColorSpace,
+    AvifChroma,
+    TiffCompression,
+    IcoSizes,
+    PnmKind,
+    TgaRle,
+    PngOptimize,
+    GifColors,
+    GifSpeed,
+    GifDither,
+    GifAlphaThreshold,
+    Metadata,
+}
 
-#[derive(Default)]
 pub struct OptionsPanel {
     config: Config,
+    pub encoding: EncodeOptions,
+    selected: usize,
+}
+
+impl Default for OptionsPanel {
+    fn default() -> Self {
+        Self {
+            config: Config::default(),
+            encoding: EncodeOptions::new(ImageFormat::Png, 90),
+            selected: 0,
+        }
+    }
 }
 
 impl OptionsPanel {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Rows shown for the current output format.
+    fn rows(&self) -> Vec<OptionRow> {
+        let mut rows = vec![OptionRow::Format, OptionRow::Quality];
+        if self.encoding.supports_target_size() {
+            rows.push(OptionRow::TargetSize);
+        }
+        match self.encoding.format {
+            ImageFormat::WebP => rows.push(OptionRow::WebpLossless),
+            ImageFormat::Avif => {
+                rows.push(OptionRow::AvifSpeed);
+                rows.push(OptionRow::AvifColorSpace);
+                if self.encoding.avif_color_space != ColorSpace::Srgb {
+                    rows.push(OptionRow::AvifChroma);
+                }
+            }
+            ImageFormat::Tiff => rows.push(OptionRow::TiffCompression),
+            ImageFormat::Ico => rows.push(OptionRow::IcoSizes),
+            ImageFormat::Pnm => rows.push(OptionRow::PnmKind),
+            ImageFormat::Tga => rows.push(OptionRow::TgaRle),
+            ImageFormat::Png => rows.push(OptionRow::PngOptimize),
+            ImageFormat::Gif => {
+                rows.push(OptionRow::GifColors);
+                rows.push(OptionRow::GifSpeed);
+                rows.push(OptionRow::GifDither);
+                rows.push(OptionRow::GifAlphaThreshold);
+            }
+            _ => {}
+        }
+        rows.push(OptionRow::Metadata);
+        rows
+    }
+
+    /// Steps the value of `row` forward or backward.
+    fn adjust(&mut self, row: OptionRow, forward: bool) {
+        let encoding = &mut self.encoding;
+        match row {
+            OptionRow::Format => {
+                encoding.format = cycle(&FORMATS, encoding.format, forward);
+            }
+            OptionRow::Quality => {
+                encoding.quality = if forward {
+                    encoding.quality.saturating_add(5).min(100)
+                } else {
+                    encoding.quality.saturating_sub(5).max(1)
+                };
+            }
+            OptionRow::TargetSize => {
+                encoding.target_size = cycle(&TARGET_SIZES, encoding.target_size, forward);
+            }
+            OptionRow::WebpLossless => {
+                encoding.webp_lossless = !encoding.webp_lossless;
+            }
+            OptionRow::AvifSpeed => {
+                encoding.avif_speed = if forward {
+                    (encoding.avif_speed + 1).min(10)
+                } else {
+                    encoding.avif_speed.saturating_sub(1).max(1)
+                };
+            }
+            OptionRow::AvifColorSpace => {
+                encoding.avif_color_space = if encoding.avif_color_space == ColorSpace::Srgb {
+                    ColorSpace::Bt709
+                } else {
+                    // RGB planes cannot be subsampled
+                    encoding.avif_chroma = ChromaSubsampling::Yuv444;
+                    ColorSpace::Srgb
+                };
+            }
+            OptionRow::AvifChroma => {
+                encoding.avif_chroma = cycle(ChromaSubsampling::value_variants(), encoding.avif_chroma, forward);
+            }
+            OptionRow::TiffCompression => {
+                encoding.tiff_compression = cycle(TiffCompression::value_variants(), encoding.tiff_compression, forward);
+            }
+            OptionRow::IcoSizes => {
+                encoding.ico_sizes = cycle(&ICO_SIZE_SETS, encoding.ico_sizes, forward);
+            }
+            OptionRow::PnmKind => {
+                encoding.pnm_kind = cycle(PnmKind::value_variants(), encoding.pnm_kind, forward);
+            }
+            OptionRow::TgaRle => {
+                encoding.tga_rle = !encoding.tga_rle;
+            }
+            OptionRow::PngOptimize => {
+                encoding.png_optimize = !encoding.png_optimize;
+            }
+            OptionRow::GifColors => {
+                encoding.gif.colors = cycle(&GIF_COLORS, encoding.gif.colors, forward);
+            }
+            OptionRow::GifSpeed => {
+                encoding.gif.speed = cycle(&GIF_SPEEDS, encoding.gif.speed, forward);
+            }
+            OptionRow::GifDither => {
+                encoding.gif.dither = cycle(Dither::value_variants(), encoding.gif.dither, forward);
+            }
+            OptionRow::GifAlphaThreshold => {
+                encoding.gif.alpha_threshold = cycle(&GIF_ALPHA_THRESHOLDS, encoding.gif.alpha_threshold, forward);
+            }
+            OptionRow::Metadata => {
+                encoding.metadata = cycle(&METADATA_POLICIES, encoding.metadata, forward);
+            }
+        }
+        if !encoding.supports_target_size() {
+            encoding.target_size = None;
+        }
+    }
+
+    fn row_label(&self, row: OptionRow) -> String {
+        let encoding = &self.encoding;
+        match row {
+            OptionRow::Format => format!("< {:?} >", encoding.format),
+            OptionRow::Quality if encoding.format == ImageFormat::WebP && encoding.webp_lossless => {
+                "Quality: lossless".to_string()
+            }
+            OptionRow::Quality if encoding.supports_target_size() && encoding.target_size.is_some() => {
+                "Quality: auto".to_string()
+            }
+            OptionRow::Quality => format!("Quality: {}", encoding.quality),
+            OptionRow::TargetSize =>
+                match encoding.target_size {
+                    Some(size) => format!("Target size: {}", bytesize::ByteSize(size)),
+                    None => "Target size: off".to_string(),
+                }
+            OptionRow::WebpLossless => format!("Lossless: {}", encoding.webp_lossless),
+            OptionRow::AvifSpeed => format!("Speed: {}", encoding.avif_speed),
+            OptionRow::AvifColorSpace => {
+                let model = if encoding.avif_color_space == ColorSpace::Srgb { "RGB" } else { "YCbCr" };
+                format!("Color model: {model}")
+            }
+            OptionRow::AvifChroma => format!("Chroma: {}", encoding.avif_chroma.label()),
+            OptionRow::TiffCompression => format!("Compression: {:?}", encoding.tiff_compression),
+            OptionRow::IcoSizes => format!("Sizes: {}", encoding.ico_sizes),
+            OptionRow::PnmKind => format!("Variant: {:?}", encoding.pnm_kind),
+            OptionRow::TgaRle => format!("RLE: {}", encoding.tga_rle),
+            OptionRow::PngOptimize => format!("Try all filters: {}", encoding.png_optimize),
+            OptionRow::GifColors => format!("Colors: {}", encoding.gif.colors),
+            OptionRow::GifSpeed => format!("Speed: {}", encoding.gif.speed),
+            OptionRow::GifDither => format!("Dither: {:?}", encoding.gif.dither),
+            OptionRow::GifAlphaThreshold => format!("Alpha threshold: {}", encoding.gif.alpha_threshold),
+            OptionRow::Metadata => format!("Metadata: {}", encoding.metadata.label()),
+        }
+    }
+
+    fn line(&self, row: OptionRow, highlight: bool) -> Line<'static> {
+        let style = if highlight {
+            Style::default().add_modifier(Modifier::REVERSED)
+        } else {
+            Style::default()
+        };
+        Line::from(Span::styled(self.row_label(row), style))
+    }
+}
+
+/// The value after (or before) `current` in `values`, wrapping around.
+pub fn cycle<T: Copy + PartialEq>(values: &[T], current: T, forward: bool) -> T {
+    let position = values.iter().position(|value| *value == current).unwrap_or_default();
+    let next = if forward {
+        (position + 1) % values.len()
+    } else {
+        (position + values.len() - 1) % values.len()
+    };
+    values[next]
 }
 
 impl Component for OptionsPanel {
@@ -48,29 +312,35 @@ impl Component for OptionsPanel {
             .title(Span::styled("Options", Style::default().add_modifier(Modifier::BOLD)));
         f.render_widget(panel.clone(), area);
 
-        // Placeholder for options
+        let rows = self.rows();
+        let selected = rows.get(self.selected).copied().filter(|_| focused);
+        let (format_lines, quality_lines): (Vec<_>, Vec<_>) = rows
+            .iter()
+            .map(|row| (*row, self.line(*row, selected == Some(*row))))
+            .partition(|(row, _)| *row == OptionRow::Format);
+
         let options_area = panel.inner(area);
         let chunks = Layout::default()
             .direction(Direction::Vertical)
             .constraints([
                 Constraint::Length(3),
                 Constraint::Length(3),
-                Constraint::Length(3),
+                Constraint::Length(quality_lines.len() as u16 + 2),
                 Constraint::Min(0),
             ])
             .split(options_area);
 
-        // Placeholder for Output Format
         let output_format_block = Block::default().title("Output Format").borders(Borders::ALL);
-        f.render_widget(output_format_block, chunks[0]);
+        let format_lines: Vec<Line> = format_lines.into_iter().map(|(_, line)| line).collect();
+        f.render_widget(Paragraph::new(format_lines).block(output_format_block), chunks[0]);
 
         // Placeholder for Resize Options
         let resize_options_block = Block::default().title("Resize Options").borders(Borders::ALL);
         f.render_widget(resize_options_block, chunks[1]);
 
-        // Placeholder for Quality/Compression
         let quality_block = Block::default().title("Quality/Compression").borders(Borders::ALL);
-        f.render_widget(quality_block, chunks[2]);
+        let quality_lines: Vec<Line> = quality_lines.into_iter().map(|(_, line)| line).collect();
+        f.render_widget(Paragraph::new(quality_lines).block(quality_block), chunks[2]);
 
         Ok(())
     }
@@ -79,7 +349,96 @@ impl Component for OptionsPanel {
         Ok(None)
     }
 
-    fn handle_events(&mut self, _event: Option<Event>) -> Result<Option<Action>> {
+    fn handle_events(&mut self, event: Option<Event>) -> Result<Option<Action>> {
+        let Some(Event::Key(key)) = event else {
+            return Ok(None);
+        };
+        let Some(action) = self.config.keybindings
+            .get(&crate::app::Mode::Home)
+            .and_then(|keybindings| keybindings.get(&vec![key])) else {
+            return Ok(None);
+        };
+        let rows = self.rows();
+        match action {
+            Action::Down => {
+                self.selected = (self.selected + 1).min(rows.len() - 1);
+            }
+            Action::Up => {
+                self.selected = self.selected.saturating_sub(1);
+            }
+            Action::Left | Action::Right => {
+                if let Some(row) = rows.get(self.selected).copied() {
+                    self.adjust(row, *action == Action::Right);
+                }
+                // Switching formats can hide the row the cursor was on
+                self.selected = self.selected.min(self.rows().len() - 1);
+            }
+            _ => {}
+        }
         Ok(None)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rows_follow_format() {
+        let mut panel = OptionsPanel::new();
+        assert_eq!(
+            panel.rows(),
+            vec![OptionRow::Format, OptionRow::Quality, OptionRow::PngOptimize, OptionRow::Metadata]
+        );
+        panel.adjust(OptionRow::Format, true);
+        panel.adjust(OptionRow::Format, true);
+        assert_eq!(panel.encoding.format, ImageFormat::WebP);
+        assert!(panel.rows().contains(&OptionRow::WebpLossless));
+        assert!(panel.rows().contains(&OptionRow::TargetSize));
+        panel.adjust(OptionRow::TargetSize, true);
+        panel.adjust(OptionRow::WebpLossless, true);
+        assert!(!panel.rows().contains(&OptionRow::TargetSize));
+        assert_eq!(panel.encoding.target_size, None);
+    }
+
+    #[test]
+    fn test_adjust_clamps_values() {
+        let mut panel = OptionsPanel::new();
+        panel.encoding.quality = 98;
+        panel.adjust(OptionRow::Quality, true);
+        assert_eq!(panel.encoding.quality, 100);
+        panel.encoding.avif_speed = 1;
+        panel.adjust(OptionRow::AvifSpeed, false);
+        assert_eq!(panel.encoding.avif_speed, 1);
+        panel.adjust(OptionRow::Metadata, false);
+        assert_eq!(panel.encoding.metadata, MetadataPolicy::StripAll);
+    }
+
+    #[test]
+    fn test_format_specific_rows() {
+        let mut panel = OptionsPanel::new();
+        panel.adjust(OptionRow::Format, false);
+        assert_eq!(panel.encoding.format, ImageFormat::Farbfeld);
+        panel.encoding.format = ImageFormat::Ico;
+        assert!(panel.rows().contains(&OptionRow::IcoSizes));
+        panel.adjust(OptionRow::IcoSizes, false);
+        assert_eq!(panel.encoding.ico_sizes, IcoSizes::all());
+        panel.encoding.format = ImageFormat::Tiff;
+        panel.adjust(OptionRow::TiffCompression, true);
+        assert_eq!(panel.row_label(OptionRow::TiffCompression), "Compression: Deflate");
+        panel.encoding.format = ImageFormat::Gif;
+        assert!(panel.rows().contains(&OptionRow::GifDither));
+        panel.adjust(OptionRow::GifColors, true);
+        assert_eq!(panel.row_label(OptionRow::GifColors), "Colors: 128");
+        panel.adjust(OptionRow::GifSpeed, false);
+        assert_eq!(panel.row_label(OptionRow::GifSpeed), "Speed: 5");
+        panel.adjust(OptionRow::GifAlphaThreshold, true);
+        assert_eq!(panel.row_label(OptionRow::GifAlphaThreshold), "Alpha threshold: 192");
+        panel.encoding.format = ImageFormat::Avif;
+        panel.adjust(OptionRow::AvifChroma, false);
+        assert_eq!(panel.row_label(OptionRow::AvifChroma), "Chroma: 4:2:0");
+        panel.adjust(OptionRow::AvifColorSpace, true);
+        assert!(!panel.rows().contains(&OptionRow::AvifChroma));
+        assert_eq!(panel.encoding.avif_chroma, ChromaSubsampling::Yuv444);
+    }
+}
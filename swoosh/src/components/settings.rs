@@ -1,9 +1,32 @@
 ﻿// src/ui/settings.rs
 
+use bitflags::bitflags;
+use clap::ValueEnum;
+use color_eyre::Result;
 use ratatui::{
-    layout::{Constraint, Direction, Layout, Rect}, style::{Color, Modifier, Style}, text::{Line, Span}, widgets::{Block, Borders, List, ListItem, ListState, Paragraph, StatefulWidget}
+    layout::Rect,
+    style::{ Color, Modifier, Style },
+    text::{ Line, Span },
+    widgets::{ Block, Borders, Clear, Paragraph },
+    Frame,
+};
+
+use crate::{
+    action::Action,
+    components::{
+        image_processing::{
+            quantize::{ Dither, QuantizeOptions },
+            transform::{
+                AspectRatio, Gravity, Interpolation, KernelPreset, ResizeFilter, ResizeMode, ResizeOptions,
+                ResizeSize, RotateOptions,
+            },
+        },
+        options::cycle,
+        Component,
+    },
+    config::Config,
+    tui::Event,
 };
-use bitflags::bitflags;
 
 
 bitflags! {
@@ -18,20 +41,118 @@ bitflags! {
         const BRIGHTEN = 0b10000000;
         const CONTRAST = 0b100000000;
         const HUEROTATE = 0b1000000000;
+        const AUTO_ORIENT = 0b10000000000;
+        const GRAYSCALE = 0b100000000000;
+        const DESKEW = 0b1000000000000;
+        const TRIM = 0b10000000000000;
+        const SMART_CROP = 0b100000000000000;
+        const POSTERIZE = 0b1000000000000000;
     }
 }
 
+/// Target sizes the resize size row cycles through.
+const RESIZE_SIZES: [ResizeSize; 7] = [
+    ResizeSize::Pixels { width: 800, height: 600 },
+    ResizeSize::Pixels { width: 1920, height: 1080 },
+    ResizeSize::Pixels { width: 1280, height: 0 },
+    ResizeSize::Pixels { width: 640, height: 0 },
+    ResizeSize::Percent(50.0),
+    ResizeSize::Percent(25.0),
+    ResizeSize::Megapixels(2.0),
+];
+
+/// Aspect ratios the smart crop row cycles through.
+const ASPECT_RATIOS: [AspectRatio; 7] = [
+    AspectRatio { width: 1, height: 1 },
+    AspectRatio { width: 4, height: 3 },
+    AspectRatio { width: 3, height: 2 },
+    AspectRatio { width: 16, height: 9 },
+    AspectRatio { width: 3, height: 4 },
+    AspectRatio { width: 2, height: 3 },
+    AspectRatio { width: 9, height: 16 },
+];
 
+/// Palette sizes the posterize colors row cycles through.
+const POSTERIZE_COLORS: [u16; 8] = [256, 128, 64, 32, 16, 8, 4, 2];
+
+/// One parameter of a transformation, shown below it while it is enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Param {
+    DeskewMaxAngle,
+    TrimTolerance,
+    ResizeSize,
+    ResizeMode,
+    ResizeFilter,
+    LinearLight,
+    RotateDegrees,
+    RotateInterpolation,
+    RotateExpand,
+    FlipHorizontal,
+    FlipVertical,
+    BlurSigma,
+    UnsharpenSigma,
+    UnsharpenThreshold,
+    CropX,
+    CropY,
+    CropWidth,
+    CropHeight,
+    SmartCropAspect,
+    SmartCropGravity,
+    Kernel,
+    BrightenValue,
+    ContrastValue,
+    HueRotateDegrees,
+    PosterizeColors,
+    PosterizeDither,
+}
+
+impl Param {
+    /// Parameters of the transformation `flag`, in display order.
+    fn of(flag: TransformFlags) -> &'static [Param] {
+        match flag {
+            TransformFlags::DESKEW => &[Param::DeskewMaxAngle],
+            TransformFlags::TRIM => &[Param::TrimTolerance],
+            TransformFlags::RESIZE => &[Param::ResizeSize, Param::ResizeMode, Param::ResizeFilter, Param::LinearLight],
+            TransformFlags::ROTATE => &[Param::RotateDegrees, Param::RotateInterpolation, Param::RotateExpand],
+            TransformFlags::FLIP => &[Param::FlipHorizontal, Param::FlipVertical],
+            TransformFlags::BLUR => &[Param::BlurSigma, Param::LinearLight],
+            TransformFlags::UNSHARPEN => &[Param::UnsharpenSigma, Param::UnsharpenThreshold],
+            TransformFlags::CROP => &[Param::CropX, Param::CropY, Param::CropWidth, Param::CropHeight],
+            TransformFlags::SMART_CROP => &[Param::SmartCropAspect, Param::SmartCropGravity],
+            TransformFlags::FILTER3X3 => &[Param::Kernel],
+            TransformFlags::BRIGHTEN => &[Param::BrightenValue],
+            TransformFlags::CONTRAST => &[Param::ContrastValue],
+            TransformFlags::HUEROTATE => &[Param::HueRotateDegrees],
+            TransformFlags::POSTERIZE => &[Param::PosterizeColors, Param::PosterizeDither],
+            _ => &[],
+        }
+    }
+}
+
+/// One line of the panel: a transformation and its checkbox, or one of its parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SettingRow {
+    Transform(TransformFlags),
+    Param(Param),
+}
 
 pub struct SettingsPanel<'a> {
+    config: Config,
+    selected: usize,
     pub transform_flags: TransformFlags,
     pub items: Vec<(&'a str, TransformFlags)>,
-    pub resize_width: u32,
-    pub resize_height: u32,
-    pub preserve_aspect_ratio: bool,
+    pub resize_options: ResizeOptions,
+
+    // Run resize and blur in linear light
+    pub linear_light: bool,
+
+    // Auto-trim and auto-deskew
+    pub trim_tolerance: f32,
+    pub deskew_max_degrees: f32,
 
     // Rotations
-    pub rotate_degrees: i32,
+    pub rotate_degrees: f32,
+    pub rotate_options: RotateOptions,
 
     // Flips
     pub flip_horizontal: bool,
@@ -45,37 +166,45 @@ pub struct SettingsPanel<'a> {
     pub crop_y: u32,
     pub crop_width: u32,
     pub crop_height: u32,
+    pub smart_crop_aspect: AspectRatio,
+    pub smart_crop_gravity: Gravity,
     pub filter3x3_kernel: [f32; 9],
     pub brighten_value: i32,
     pub contrast_value: f32,
     pub huerotate_value: i32,
+    pub posterize_options: QuantizeOptions,
 }
 
-pub struct SettingsPanelWidget<'a> {
-    settings: &'a mut SettingsPanel<'a>
-}
-
-
 impl<'a> SettingsPanel<'a>  {
     pub fn new() -> Self {
         SettingsPanel {
-            transform_flags: TransformFlags::empty(),
+            config: Config::default(),
+            selected: 0,
+            transform_flags: TransformFlags::AUTO_ORIENT,
             items: vec![
+                ("Auto-orient", TransformFlags::AUTO_ORIENT),
+                ("Deskew", TransformFlags::DESKEW),
+                ("Trim Borders", TransformFlags::TRIM),
                 ("Resize", TransformFlags::RESIZE),
                 ("Rotate", TransformFlags::ROTATE),
                 ("Flip", TransformFlags::FLIP),
                 ("Blur", TransformFlags::BLUR),
                 ("Unsharpen", TransformFlags::UNSHARPEN),
                 ("Crop", TransformFlags::CROP),
+                ("Smart Crop", TransformFlags::SMART_CROP),
                 ("Filter 3x3", TransformFlags::FILTER3X3),
                 ("Brighten", TransformFlags::BRIGHTEN),
                 ("Contrast", TransformFlags::CONTRAST),
                 ("Hue Rotate", TransformFlags::HUEROTATE),
+                ("Grayscale", TransformFlags::GRAYSCALE),
+                ("Posterize", TransformFlags::POSTERIZE),
                 ],
-            resize_width: 800,
-            resize_height: 600,
-            preserve_aspect_ratio: true,
-            rotate_degrees: 90,
+            resize_options: ResizeOptions::default(),
+            linear_light: false,
+            trim_tolerance: 10.0,
+            deskew_max_degrees: 15.0,
+            rotate_degrees: 90.0,
+            rotate_options: RotateOptions::default(),
             flip_horizontal: false,
             flip_vertical: false,
             blur_sigma: 1.0,
@@ -85,112 +214,290 @@ impl<'a> SettingsPanel<'a>  {
             crop_y: 0,
             crop_width: 100,
             crop_height: 100,
-            filter3x3_kernel: [0.0; 9],
+            smart_crop_aspect: AspectRatio::new(1, 1),
+            smart_crop_gravity: Gravity::Center,
+            filter3x3_kernel: KernelPreset::Sharpen.kernel(),
             brighten_value: 0,
             contrast_value: 0.0,
             huerotate_value: 0,
+            posterize_options: QuantizeOptions { colors: 16, ..QuantizeOptions::default() },
         }
     }
 
+    /// Every transformation, each enabled one followed by its parameters.
+    fn rows(&self) -> Vec<SettingRow> {
+        let mut rows = Vec::new();
+        for (_, flag) in &self.items {
+            rows.push(SettingRow::Transform(*flag));
+            if self.transform_flags.contains(*flag) {
+                rows.extend(Param::of(*flag).iter().map(|param| SettingRow::Param(*param)));
+            }
+        }
+        rows
+    }
+
+    /// Toggles a transformation, or steps the value of a parameter forward or backward.
+    fn adjust(&mut self, row: SettingRow, forward: bool) {
+        let param = match row {
+            SettingRow::Transform(flag) => {
+                self.transform_flags.toggle(flag);
+                return;
+            }
+            SettingRow::Param(param) => param,
+        };
+        let step = |forward: bool, by: f32| if forward { by } else { -by };
+        match param {
+            Param::DeskewMaxAngle => {
+                self.deskew_max_degrees = (self.deskew_max_degrees + step(forward, 5.0)).clamp(5.0, 45.0);
+            }
+            Param::TrimTolerance => {
+                self.trim_tolerance = (self.trim_tolerance + step(forward, 5.0)).clamp(0.0, 100.0);
+            }
+            Param::ResizeSize => {
+                self.resize_options.size = cycle(&RESIZE_SIZES, self.resize_options.size, forward);
+            }
+            Param::ResizeMode => {
+                self.resize_options.mode = cycle(ResizeMode::value_variants(), self.resize_options.mode, forward);
+            }
+            Param::ResizeFilter => {
+                self.resize_options.filter = cycle(ResizeFilter::value_variants(), self.resize_options.filter, forward);
+            }
+            Param::LinearLight => {
+                self.linear_light = !self.linear_light;
+            }
+            Param::RotateDegrees => {
+                // Wraps within -180..=180 so both directions stay reachable
+                let degrees = self.rotate_degrees + step(forward, 1.0);
+                self.rotate_degrees = (degrees + 180.0).rem_euclid(360.0) - 180.0;
+            }
+            Param::RotateInterpolation => {
+                let interpolation = self.rotate_options.interpolation;
+                self.rotate_options.interpolation = cycle(Interpolation::value_variants(), interpolation, forward);
+            }
+            Param::RotateExpand => {
+                self.rotate_options.expand = !self.rotate_options.expand;
+            }
+            Param::FlipHorizontal => {
+                self.flip_horizontal = !self.flip_horizontal;
+            }
+            Param::FlipVertical => {
+                self.flip_vertical = !self.flip_vertical;
+            }
+            Param::BlurSigma => {
+                self.blur_sigma = (self.blur_sigma + step(forward, 0.5)).clamp(0.5, 50.0);
+            }
+            Param::UnsharpenSigma => {
+                self.unsharpen_sigma = (self.unsharpen_sigma + step(forward, 0.5)).clamp(0.5, 50.0);
+            }
+            Param::UnsharpenThreshold => {
+                self.unsharpen_threshold = (self.unsharpen_threshold + step(forward, 1.0) as i32).clamp(0, 255);
+            }
+            Param::CropX => {
+                self.crop_x = step_u32(self.crop_x, forward, 10, 0);
+            }
+            Param::CropY => {
+                self.crop_y = step_u32(self.crop_y, forward, 10, 0);
+            }
+            Param::CropWidth => {
+                self.crop_width = step_u32(self.crop_width, forward, 10, 10);
+            }
+            Param::CropHeight => {
+                self.crop_height = step_u32(self.crop_height, forward, 10, 10);
+            }
+            Param::SmartCropAspect => {
+                self.smart_crop_aspect = cycle(&ASPECT_RATIOS, self.smart_crop_aspect, forward);
+            }
+            Param::SmartCropGravity => {
+                self.smart_crop_gravity = cycle(Gravity::value_variants(), self.smart_crop_gravity, forward);
+            }
+            Param::Kernel => {
+                let preset = KernelPreset::from_kernel(&self.filter3x3_kernel).unwrap_or_default();
+                self.filter3x3_kernel = cycle(&KernelPreset::ALL, preset, forward).kernel();
+            }
+            Param::BrightenValue => {
+                self.brighten_value = (self.brighten_value + step(forward, 5.0) as i32).clamp(-255, 255);
+            }
+            Param::ContrastValue => {
+                self.contrast_value = (self.contrast_value + step(forward, 5.0)).clamp(-100.0, 100.0);
+            }
+            Param::HueRotateDegrees => {
+                let degrees = self.huerotate_value + step(forward, 15.0) as i32;
+                self.huerotate_value = (degrees + 180).rem_euclid(360) - 180;
+            }
+            Param::PosterizeColors => {
+                let colors = self.posterize_options.colors;
+                self.posterize_options.colors = cycle(&POSTERIZE_COLORS, colors, forward);
+            }
+            Param::PosterizeDither => {
+                self.posterize_options.dither = cycle(Dither::value_variants(), self.posterize_options.dither, forward);
+            }
+        }
+    }
 
+    fn row_label(&self, row: SettingRow) -> String {
+        let param = match row {
+            SettingRow::Transform(flag) => {
+                let name = self.items.iter().find(|(_, item)| *item == flag).map_or("", |(name, _)| *name);
+                let check = if self.transform_flags.contains(flag) { "[x]" } else { "[ ]" };
+                return format!("{check} {name}");
+            }
+            SettingRow::Param(param) => param,
+        };
+        let value = match param {
+            Param::DeskewMaxAngle => format!("Max Angle: {}", self.deskew_max_degrees),
+            Param::TrimTolerance => format!("Tolerance: {}", self.trim_tolerance),
+            Param::ResizeSize => format!("Size: {}", self.resize_options.size),
+            Param::ResizeMode => format!("Mode: {:?}", self.resize_options.mode),
+            Param::ResizeFilter => format!("Filter: {:?}", self.resize_options.filter),
+            Param::LinearLight => format!("Linear Light: {}", self.linear_light),
+            Param::RotateDegrees => format!("Degrees: {}", self.rotate_degrees),
+            Param::RotateInterpolation => format!("Interpolation: {:?}", self.rotate_options.interpolation),
+            Param::RotateExpand => format!("Expand Canvas: {}", self.rotate_options.expand),
+            Param::FlipHorizontal => format!("Horizontal: {}", self.flip_horizontal),
+            Param::FlipVertical => format!("Vertical: {}", self.flip_vertical),
+            Param::BlurSigma => format!("Sigma: {}", self.blur_sigma),
+            Param::UnsharpenSigma => format!("Sigma: {}", self.unsharpen_sigma),
+            Param::UnsharpenThreshold => format!("Threshold: {}", self.unsharpen_threshold),
+            Param::CropX => format!("X: {}", self.crop_x),
+            Param::CropY => format!("Y: {}", self.crop_y),
+            Param::CropWidth => format!("Width: {}", self.crop_width),
+            Param::CropHeight => format!("Height: {}", self.crop_height),
+            Param::SmartCropAspect => format!("Aspect Ratio: {}", self.smart_crop_aspect),
+            Param::SmartCropGravity => format!("Gravity: {:?}", self.smart_crop_gravity),
+            Param::Kernel => match KernelPreset::from_kernel(&self.filter3x3_kernel) {
+                Some(preset) => format!("Kernel: {:?}", preset),
+                None => format!("Kernel: {:?}", self.filter3x3_kernel),
+            },
+            Param::BrightenValue => format!("Value: {}", self.brighten_value),
+            Param::ContrastValue => format!("Value: {}", self.contrast_value),
+            Param::HueRotateDegrees => format!("Degrees: {}", self.huerotate_value),
+            Param::PosterizeColors => format!("Colors: {}", self.posterize_options.colors),
+            Param::PosterizeDither => format!("Dither: {:?}", self.posterize_options.dither),
+        };
+        format!("    {value}")
+    }
 
-    pub fn render(&'a mut self) -> SettingsPanelWidget<'a> {
-        SettingsPanelWidget {
-            settings: self,
+    fn line(&self, row: SettingRow, highlight: bool) -> Line<'static> {
+        let mut style = match row {
+            SettingRow::Transform(flag) if self.transform_flags.contains(flag) => Style::default().fg(Color::Green),
+            SettingRow::Transform(_) => Style::default(),
+            SettingRow::Param(_) => Style::default().fg(Color::Gray),
+        };
+        if highlight {
+            style = style.add_modifier(Modifier::REVERSED);
         }
+        Line::from(Span::styled(self.row_label(row), style))
     }
 }
 
+/// `value` moved by `by` in either direction, never below `min`.
+fn step_u32(value: u32, forward: bool, by: u32, min: u32) -> u32 {
+    let value = if forward { value.saturating_add(by) } else { value.saturating_sub(by) };
+    value.max(min)
+}
 
+impl Component for SettingsPanel<'_> {
+    fn register_config_handler(&mut self, config: Config) -> Result<()> {
+        self.config = config;
+        Ok(())
+    }
 
-impl<'a> StatefulWidget for SettingsPanelWidget<'a> {
-    type State = ListState;
+    fn draw(&mut self, f: &mut Frame, area: Rect, focused: bool) -> Result<()> {
+        f.render_widget(Clear, area);
+        let style_key = if focused { "focused" } else { "default" };
+        let block_style = self.config.styles
+            .get(&crate::app::Mode::Home)
+            .and_then(|x| x.get(style_key))
+            .copied()
+            .unwrap_or_default();
+        let panel = Block::default()
+            .borders(Borders::ALL)
+            .style(block_style)
+            .title(Span::styled("Transformations", Style::default().add_modifier(Modifier::BOLD)));
 
-    fn render(mut self, area: Rect, buf: &mut ratatui::buffer::Buffer, state: &mut Self::State) {
-        let items: Vec<ListItem> = self.settings
-            .items
+        let rows = self.rows();
+        let lines: Vec<Line> = rows
             .iter()
-            .map(|(name, flag)| {
-                let mut spans = vec![Span::raw(format!("{} ", name))];
-                if self.settings.transform_flags.contains(*flag) {
-                    spans.push(Span::styled("[x]", Style::default().fg(Color::Green))); // Indicate enabled
-                } else {
-                    spans.push(Span::styled("[ ]", Style::default().fg(Color::Gray))); // Indicate disabled
-                }
-                ListItem::new(Line::from(spans))
-            })
+            .enumerate()
+            .map(|(index, row)| self.line(*row, focused && index == self.selected))
             .collect();
-        let list = List::new(items) // existing list widget creation
-            .block(Block::default().borders(Borders::ALL).title("Transformations"))
-            .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
-            .highlight_symbol("> ");
-
-        let mut settings_text = Vec::new(); // Start with an empty Vec
-
-
-        // Add settings text only for ENABLED transformations
-        if let Some(selected_index) = state.selected() {
-            let (_, selected_flag) = self.settings.items[selected_index];
-
-            if self.settings.transform_flags.contains(selected_flag) { // Only if enabled
-                match selected_flag {
-                    TransformFlags::RESIZE => {
-                        settings_text.push(Line::from(vec![Span::raw(format!("Width: {}", self.settings.resize_width))]));
-                        settings_text.push(Line::from(vec![Span::raw(format!("Height: {}", self.settings.resize_height))]));
-                        settings_text.push(Line::from(vec![Span::raw(format!("Preserve Aspect Ratio: {}", self.settings.preserve_aspect_ratio))]));
-                    }
-                    TransformFlags::ROTATE => {
-                        settings_text.push(Line::from(vec![Span::raw(format!("Degrees: {}", self.settings.rotate_degrees))]));
-                    }
-                    TransformFlags::FLIP => {
-                        settings_text.push(Line::from(vec![Span::raw(format!("Horizontal: {}", self.settings.flip_horizontal))]));
-                        settings_text.push(Line::from(vec![Span::raw(format!("Vertical: {}", self.settings.flip_vertical))]));
-                    }
-                    TransformFlags::BLUR => {
-                        settings_text.push(Line::from(vec![Span::raw(format!("Sigma: {}", self.settings.blur_sigma))]));
-                    }
-                    TransformFlags::UNSHARPEN => {
-                        settings_text.push(Line::from(vec![Span::raw(format!("Sigma: {}", self.settings.unsharpen_sigma))]));
-                        settings_text.push(Line::from(vec![Span::raw(format!("Threshold: {}", self.settings.unsharpen_threshold))]));
-                    }
-                    TransformFlags::CROP => {
-                        settings_text.push(Line::from(vec![Span::raw(format!("X: {}", self.settings.crop_x))]));
-                        settings_text.push(Line::from(vec![Span::raw(format!("Y: {}", self.settings.crop_y))]));
-                        settings_text.push(Line::from(vec![Span::raw(format!("Width: {}", self.settings.crop_width))]));
-                        settings_text.push(Line::from(vec![Span::raw(format!("Height: {}", self.settings.crop_height))]));
-                    }
-                    TransformFlags::FILTER3X3 => {
-                        settings_text.extend(self.settings.filter3x3_kernel.iter().enumerate().map(|(i, &val)| {
-                            Line::from(vec![Span::raw(format!("Kernel[{}]: {}", i, val))])
-                        }));
-                    }
-                    TransformFlags::BRIGHTEN => {
-                        settings_text.push(Line::from(vec![Span::raw(format!("Value: {}", self.settings.brighten_value))]));
-                    }
-                    TransformFlags::CONTRAST => {
-                        settings_text.push(Line::from(vec![Span::raw(format!("Value: {}", self.settings.contrast_value))]));
-                    }
-                    TransformFlags::HUEROTATE => {
-                        settings_text.push(Line::from(vec![Span::raw(format!("Value: {}", self.settings.huerotate_value))]));
-                    }
-                    _ => {}
+        // Scroll just far enough to keep the cursor visible
+        let height = panel.inner(area).height.max(1) as usize;
+        let offset = self.selected.saturating_sub(height - 1) as u16;
+        f.render_widget(Paragraph::new(lines).block(panel).scroll((offset, 0)), area);
+        Ok(())
+    }
+
+    fn update(&mut self, _action: Action) -> Result<Option<Action>> {
+        Ok(None)
+    }
+
+    fn handle_events(&mut self, event: Option<Event>) -> Result<Option<Action>> {
+        let Some(Event::Key(key)) = event else {
+            return Ok(None);
+        };
+        let Some(action) = self.config.keybindings
+            .get(&crate::app::Mode::Home)
+            .and_then(|keybindings| keybindings.get(&vec![key])) else {
+            return Ok(None);
+        };
+        let rows = self.rows();
+        match action {
+            Action::Down => {
+                self.selected = (self.selected + 1).min(rows.len() - 1);
+            }
+            Action::Up => {
+                self.selected = self.selected.saturating_sub(1);
+            }
+            Action::Left | Action::Right => {
+                if let Some(row) = rows.get(self.selected).copied() {
+                    self.adjust(row, *action == Action::Right);
                 }
+                // Disabling a transformation hides its parameters below the cursor
+                self.selected = self.selected.min(self.rows().len() - 1);
             }
+            _ => {}
         }
+        Ok(None)
+    }
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let settings_paragraph = if settings_text.is_empty() {
-            Paragraph::new("Select a transformation to configure").style(Style::default().fg(Color::Gray))
-        } else {
-            Paragraph::new(settings_text).style(Style::default().fg(Color::White))
-        };
-
+    #[test]
+    fn test_rows_follow_enabled_transforms() {
+        let mut panel = SettingsPanel::new();
+        assert_eq!(panel.rows().len(), panel.items.len());
+        panel.adjust(SettingRow::Transform(TransformFlags::FLIP), true);
+        assert!(panel.transform_flags.contains(TransformFlags::FLIP));
+        let rows = panel.rows();
+        let flip = rows.iter().position(|row| *row == SettingRow::Transform(TransformFlags::FLIP)).unwrap();
+        assert_eq!(rows[flip + 1..flip + 3], [SettingRow::Param(Param::FlipHorizontal), SettingRow::Param(Param::FlipVertical)]);
+        panel.adjust(SettingRow::Param(Param::FlipVertical), true);
+        assert!(panel.flip_vertical);
+        panel.adjust(SettingRow::Transform(TransformFlags::FLIP), false);
+        assert_eq!(panel.rows().len(), panel.items.len());
+    }
 
-        let settings_layout = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([Constraint::Min(0), Constraint::Length(6)].as_ref())
-            .split(area);
+    #[test]
+    fn test_adjust_params() {
+        let mut panel = SettingsPanel::new();
+        panel.adjust(SettingRow::Param(Param::Kernel), true);
+        assert_eq!(panel.filter3x3_kernel, KernelPreset::EdgeDetect.kernel());
+        assert_eq!(panel.row_label(SettingRow::Param(Param::Kernel)), "    Kernel: EdgeDetect");
+        panel.adjust(SettingRow::Param(Param::Kernel), false);
+        panel.adjust(SettingRow::Param(Param::Kernel), false);
+        assert_eq!(panel.filter3x3_kernel, KernelPreset::BoxBlur.kernel());
 
-        
+        panel.rotate_degrees = 180.0;
+        panel.adjust(SettingRow::Param(Param::RotateDegrees), true);
+        assert_eq!(panel.rotate_degrees, -179.0);
+        panel.crop_width = 10;
+        panel.adjust(SettingRow::Param(Param::CropWidth), false);
+        assert_eq!(panel.crop_width, 10);
+        panel.adjust(SettingRow::Param(Param::ResizeSize), true);
+        assert_eq!(panel.resize_options.size, ResizeSize::Pixels { width: 1920, height: 1080 });
     }
-}
\ No newline at end of file
+}
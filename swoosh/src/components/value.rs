@@ -1,14 +1,21 @@
-﻿use std::{ fmt::Display, path::PathBuf };
+﻿use std::{ fmt::Display, path::PathBuf, sync::atomic::{ AtomicUsize, Ordering } };
 use serde::{ Serialize, Deserialize };
-use rand::Rng;
-use rand::seq::SliceRandom;
+
+/// Next [`ImageInfo::id`] handed out.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
 
 #[derive(Clone, Debug, Default)]
 pub struct ImageInfo {
+    /// Identifies the row while others are added and removed around it, unlike its position.
+    pub id: usize,
     pub path: PathBuf,
     pub filename: String,
     pub size: String,
     pub status: ImageStatus,
+    /// Extra result shown next to the status, e.g. the quality picked for a target size.
+    pub details: Option<String>,
+    /// Something the output lost, shown after the details.
+    pub warning: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Default)]
@@ -30,15 +37,21 @@ impl Display for ImageStatus {
         }
     }
 }
-impl ImageStatus {
-    fn random() -> Self {
-        let mut rng = rand::thread_rng();
-        let variants = [ImageStatus::Queued, ImageStatus::Converting, ImageStatus::Completed];
-        variants.choose(&mut rng).unwrap().clone()
+impl ImageInfo {
+    /// Status with the optional details and warning appended, as shown in the `ImageList` table.
+    pub fn status_text(&self) -> String {
+        let notes: Vec<String> = self.details
+            .iter()
+            .cloned()
+            .chain(self.warning.iter().map(|warning| format!("warning: {warning}")))
+            .collect();
+        if notes.is_empty() {
+            self.status.to_string()
+        } else {
+            format!("{} ({})", self.status, notes.join(", "))
+        }
     }
-}
 
-impl ImageInfo {
     pub fn new(path: PathBuf) -> Result<Self, String> {
         let filename = path.file_name().unwrap().to_string_lossy().to_string();
         let size = std::fs
@@ -47,14 +60,14 @@ impl ImageInfo {
             .len();
         let size = bytesize::ByteSize(size).to_string();
 
-        // give a random status
-        let status = ImageStatus::random();
-
         Ok(Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
             path,
             filename,
             size,
-            status,
+            status: ImageStatus::Queued,
+            details: None,
+            warning: None,
         })
     }
 }
@@ -11,7 +11,12 @@ use ratatui::style::{ Color, Modifier, Style };
 use serde::{ de::Deserializer, Deserialize };
 use tracing::error;
 
-use crate::{ action::Action, app::Mode };
+use crate::{
+    action::Action,
+    app::Mode,
+    components::image_processing::{ optimize::OptimizeOptions, transform::{ AlphaPolicy, KernelPreset, ResizeFilter } },
+    output::OutputPlan,
+};
 
 const CONFIG: &str = include_str!("../.config/config.json");
 
@@ -21,6 +26,28 @@ pub struct AppConfig {
     pub data_dir: PathBuf,
     #[serde(default)]
     pub config_dir: PathBuf,
+    /// Number of images converted in parallel, `0` for one per core.
+    #[serde(default)]
+    pub jobs: usize,
+    /// Memory budget in MiB for images decoded at the same time, `0` for the default.
+    #[serde(default)]
+    pub memory_budget_mb: u32,
+    /// Resampling filter used by resize unless overridden, e.g. `nearest` for pixel art.
+    #[serde(default)]
+    pub resize_filter: ResizeFilter,
+    /// Convolution kernel of the 3x3 filter unless overridden, e.g. `edge-detect`.
+    #[serde(default)]
+    pub kernel: KernelPreset,
+    /// Where converted images go: `dir`, `template`, `collision` and `mirror`.
+    #[serde(default)]
+    pub output: OutputPlan,
+    /// When `optimize` replaces a file: `min_savings`, `backup` and `trash_dir`.
+    #[serde(default)]
+    pub optimize: OptimizeOptions,
+    /// What converting to a format without alpha does with transparency: a background color like
+    /// `#ffffff`, `checkerboard` or `error`.
+    #[serde(default)]
+    pub alpha: AlphaPolicy,
 }
 
 #[derive(Clone, Debug, Default, Deserialize)]
@@ -448,6 +475,7 @@ mod tests {
     use pretty_assertions::assert_eq;
 
     use super::*;
+    use crate::components::image_processing::optimize::Backup;
 
     #[test]
     fn test_parse_style_default() {
@@ -496,6 +524,37 @@ mod tests {
         assert_eq!(color, None);
     }
 
+    #[test]
+    fn test_resize_filter_key() {
+        let config: AppConfig = json5::from_str(r#"{ "resize_filter": "catmull-rom" }"#).unwrap();
+        assert_eq!(config.resize_filter, ResizeFilter::CatmullRom);
+        assert_eq!(AppConfig::default().resize_filter, ResizeFilter::Lanczos3);
+    }
+
+    #[test]
+    fn test_kernel_key() {
+        let config: AppConfig = json5::from_str(r#"{ "kernel": "box-blur" }"#).unwrap();
+        assert_eq!(config.kernel, KernelPreset::BoxBlur);
+        assert_eq!(AppConfig::default().kernel, KernelPreset::Sharpen);
+    }
+
+    #[test]
+    fn test_optimize_keys() {
+        let config: AppConfig = json5::from_str(r#"{ "optimize": { "min_savings": 5, "backup": "trash" } }"#).unwrap();
+        assert_eq!(config.optimize.min_savings, 5.0);
+        assert_eq!(config.optimize.backup, Backup::Trash);
+        assert_eq!(config.optimize.trash_dir, None);
+    }
+
+    #[test]
+    fn test_alpha_key() {
+        let config: AppConfig = json5::from_str(r#"{ "alpha": "checkerboard" }"#).unwrap();
+        assert_eq!(config.alpha, AlphaPolicy::Checkerboard);
+        let config: AppConfig = json5::from_str(r##"{ "alpha": "#000000" }"##).unwrap();
+        assert_eq!(config.alpha, AlphaPolicy::Flatten(image::Rgb([0, 0, 0])));
+        assert!(json5::from_str::<AppConfig>(r##"{ "alpha": "#00000080" }"##).is_err());
+    }
+
     #[test]
     fn test_config() -> Result<()> {
         let c = Config::new()?;
@@ -2,16 +2,19 @@ use clap::Parser;
 use cli::Cli;
 use color_eyre::Result;
 
-use crate::app::App;
+use crate::{ app::App, config::Config, worker::Limits };
 
 mod action;
 mod app;
+mod batch;
 mod cli;
 mod components;
 mod config;
 mod errors;
 mod logging;
+mod output;
 mod tui;
+mod worker;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -19,7 +22,16 @@ async fn main() -> Result<()> {
     crate::logging::init()?;
 
     let args = Cli::parse();
-    let mut app = App::new(args.tick_rate, args.frame_rate, args.images)?;
+    let config = Config::new()?;
+    let limits = Limits::new(args.jobs.unwrap_or(config.config.jobs), config.config.memory_budget_mb);
+    if let Some(command) = args.command {
+        let summary = batch::run(command, &config.config, limits).await;
+        if summary.failed > 0 {
+            std::process::exit(libc::EXIT_FAILURE);
+        }
+        return Ok(());
+    }
+    let mut app = App::new(args.tick_rate, args.frame_rate, args.images, config, limits)?;
     app.run().await?;
     Ok(())
 }
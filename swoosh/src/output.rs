@@ -0,0 +1,377 @@
+use std::{
+    collections::HashSet,
+    fs::{ self, File },
+    io::{ self, Write },
+    path::{ Path, PathBuf },
+    sync::{ atomic::{ AtomicUsize, Ordering }, Arc, Mutex },
+    time::{ SystemTime, UNIX_EPOCH },
+};
+
+use image::{ error, ImageError, ImageResult };
+use serde::Deserialize;
+
+/// Output file name used unless a template is configured.
+pub const DEFAULT_TEMPLATE: &str = "{stem}.{ext}";
+
+/// What [`OutputPlan::resolve`] does when the planned file already exists.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Collision {
+    /// Replace the existing file.
+    Overwrite,
+    /// Keep the existing file and skip the input.
+    Skip,
+    /// Append `-1`, `-2`, ... to the file stem until the name is free.
+    #[default]
+    AutoSuffix,
+}
+
+/// Values substituted for the `{token}` placeholders of an output template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tokens {
+    pub stem: String,
+    pub ext: String,
+    pub width: u32,
+    pub height: u32,
+    /// Position of the input in the batch, starting at 1.
+    pub index: usize,
+    /// Day of the conversion as `YYYY-MM-DD`, in UTC.
+    pub date: String,
+}
+
+impl Tokens {
+    pub fn new(input: &Path, ext: &str, (width, height): (u32, u32), index: usize) -> Self {
+        Self {
+            stem: input.file_stem().unwrap_or_default().to_string_lossy().into_owned(),
+            ext: ext.to_string(),
+            width,
+            height,
+            index,
+            date: today(),
+        }
+    }
+}
+
+/// Placeholders [`render_template`] fills in.
+const TOKENS: [&str; 6] = ["{stem}", "{ext}", "{width}", "{height}", "{index}", "{date}"];
+
+/// Fails on an empty template and on braces that are not part of a known placeholder.
+pub fn validate_template(template: &str) -> ImageResult<()> {
+    let literal = TOKENS.iter().fold(template.to_string(), |rest, token| rest.replace(token, ""));
+    if template.is_empty() || literal.contains(['{', '}']) {
+        return Err(
+            ImageError::Parameter(
+                error::ParameterError::from_kind(
+                    error::ParameterErrorKind::Generic(format!("Invalid output template `{template}`"))
+                )
+            )
+        );
+    }
+    Ok(())
+}
+
+/// Fills in `{stem}`, `{ext}`, `{width}`, `{height}`, `{index}` and `{date}`.
+pub fn render_template(template: &str, tokens: &Tokens) -> String {
+    template
+        .replace("{stem}", &tokens.stem)
+        .replace("{ext}", &tokens.ext)
+        .replace("{width}", &tokens.width.to_string())
+        .replace("{height}", &tokens.height.to_string())
+        .replace("{index}", &tokens.index.to_string())
+        .replace("{date}", &tokens.date)
+}
+
+/// Where [`OutputPlan::resolve`] sends an input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Planned {
+    Write(PathBuf),
+    /// The file exists and the collision policy is [`Collision::Skip`].
+    Skip(PathBuf),
+}
+
+/// Decides where the outputs of a batch go: directory, file name and what to do on collisions.
+///
+/// Clones share the names handed out so far, so concurrent jobs never plan the same file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct OutputPlan {
+    /// Directory outputs are written to, next to each input when unset.
+    pub dir: Option<PathBuf>,
+    /// File name, may contain `/` to sort outputs into subdirectories.
+    pub template: String,
+    pub collision: Collision,
+    /// Recreate the directory tree of the inputs below `dir`, relative to their closest common
+    /// directory.
+    pub mirror: bool,
+    #[serde(skip)]
+    root: Option<PathBuf>,
+    #[serde(skip)]
+    claimed: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+impl Default for OutputPlan {
+    fn default() -> Self {
+        Self::new(DEFAULT_TEMPLATE)
+    }
+}
+
+impl OutputPlan {
+    /// Writes next to each input, named after `template`.
+    pub fn new(template: &str) -> Self {
+        Self {
+            dir: None,
+            template: template.to_string(),
+            collision: Collision::default(),
+            mirror: false,
+            root: None,
+            claimed: Arc::default(),
+        }
+    }
+
+    /// Starts a new batch over `inputs`, forgetting the names claimed by earlier batches.
+    pub fn for_inputs(mut self, inputs: &[PathBuf]) -> Self {
+        self.root = common_dir(inputs);
+        self.claimed = Arc::default();
+        self
+    }
+
+    /// Whether the file names depend on the output size, only known after decoding.
+    pub fn uses_dimensions(&self) -> bool {
+        self.template.contains("{width}") || self.template.contains("{height}")
+    }
+
+    /// The path `input` should be written to, creating its directory.
+    ///
+    /// The input itself is never planned as an output, even with [`Collision::Overwrite`].
+    pub fn resolve(&self, input: &Path, tokens: &Tokens) -> ImageResult<Planned> {
+        validate_template(&self.template)?;
+        let name = render_template(&self.template, tokens);
+        let dir = match (&self.dir, &self.root) {
+            (Some(dir), Some(root)) if self.mirror => {
+                let relative = input.parent().and_then(|parent| parent.strip_prefix(root).ok());
+                dir.join(relative.unwrap_or(Path::new("")))
+            }
+            (Some(dir), _) => dir.clone(),
+            (None, _) => input.parent().map(Path::to_path_buf).unwrap_or_default(),
+        };
+        let path = dir.join(name);
+
+        let mut claimed = self.claimed.lock().unwrap_or_else(|e| e.into_inner());
+        let taken = |path: &Path| path == input || path.exists() || claimed.contains(path);
+        let path = match self.collision {
+            _ if !taken(&path) => path,
+            Collision::Overwrite if path != input => path,
+            Collision::Skip => {
+                return Ok(Planned::Skip(path));
+            }
+            _ => (1..).map(|n| with_suffix(&path, n)).find(|path| !taken(path)).unwrap_or(path),
+        };
+        claimed.insert(path.clone());
+        drop(claimed);
+
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        Ok(Planned::Write(path))
+    }
+}
+
+/// Replaces `path` with `bytes` through a temporary file in the same directory.
+///
+/// Readers see either the old file or the complete new one, and a failed write leaves `path`
+/// untouched. An existing file keeps its permissions.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let temp = path.with_file_name(format!(".{name}.{}-{n}.tmp", std::process::id()));
+    let written = (|| {
+        let mut file = File::options().write(true).create_new(true).open(&temp)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        if let Ok(existing) = fs::metadata(path) {
+            fs::set_permissions(&temp, existing.permissions())?;
+        }
+        fs::rename(&temp, path)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+/// Fails when `output` is the file `input` was read from, through links and relative paths too.
+///
+/// Writers call this unless in-place mode was asked for, so a bad plan can never replace the
+/// source.
+pub fn ensure_not_source(input: &Path, output: &Path) -> ImageResult<()> {
+    let same = input == output ||
+        matches!((input.canonicalize(), output.canonicalize()), (Ok(a), Ok(b)) if a == b);
+    if same {
+        return Err(
+            ImageError::Parameter(
+                error::ParameterError::from_kind(
+                    error::ParameterErrorKind::Generic(
+                        format!("Refusing to overwrite the input {} outside in-place mode", input.display())
+                    )
+                )
+            )
+        );
+    }
+    Ok(())
+}
+
+/// `dir/photo.png` becomes `dir/photo-2.png` for `n = 2`.
+pub fn with_suffix(path: &Path, n: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    match path.extension() {
+        Some(extension) => path.with_file_name(format!("{stem}-{n}.{}", extension.to_string_lossy())),
+        None => path.with_file_name(format!("{stem}-{n}")),
+    }
+}
+
+/// Closest directory containing every input.
+fn common_dir(inputs: &[PathBuf]) -> Option<PathBuf> {
+    let mut parents = inputs.iter().map(|input| input.parent().unwrap_or(Path::new("")));
+    let first = parents.next()?.to_path_buf();
+    Some(
+        parents.fold(first, |common, parent| {
+            common
+                .components()
+                .zip(parent.components())
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a)
+                .collect()
+        })
+    )
+}
+
+fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() / 86_400);
+    let (year, month, day) = civil_date(days as i64);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Gregorian date of a day count since 1970-01-01, after Howard Hinnant's `civil_from_days`.
+fn civil_date(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    (year_of_era + era * 400 + i64::from(month <= 2), month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn tokens(input: &Path) -> Tokens {
+        Tokens { date: "2024-02-29".to_string(), ..Tokens::new(input, "webp", (640, 480), 3) }
+    }
+
+    #[test]
+    fn test_render_template() {
+        let tokens = tokens(Path::new("shots/photo.jpg"));
+        assert_eq!(render_template(DEFAULT_TEMPLATE, &tokens), "photo.webp");
+        assert_eq!(
+            render_template("{date}/{index}-{stem}-{width}x{height}.{ext}", &tokens),
+            "2024-02-29/3-photo-640x480.webp"
+        );
+    }
+
+    #[test]
+    fn test_civil_date() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(19_782), (2024, 2, 29));
+        assert_eq!(civil_date(-1), (1969, 12, 31));
+    }
+
+    #[test]
+    fn test_collision_policies() {
+        let temp_dir = tempdir().unwrap();
+        let input = temp_dir.path().join("photo.png");
+        std::fs::write(&input, b"").unwrap();
+        std::fs::write(temp_dir.path().join("photo.webp"), b"").unwrap();
+        let tokens = tokens(&input);
+        let plan = |collision| {
+            OutputPlan { collision, ..OutputPlan::default() }.for_inputs(std::slice::from_ref(&input))
+        };
+
+        let existing = temp_dir.path().join("photo.webp");
+        assert_eq!(plan(Collision::Overwrite).resolve(&input, &tokens).unwrap(), Planned::Write(existing.clone()));
+        assert_eq!(plan(Collision::Skip).resolve(&input, &tokens).unwrap(), Planned::Skip(existing));
+
+        // Names handed out earlier in the batch count as taken too
+        let auto_suffix = plan(Collision::AutoSuffix);
+        let first = temp_dir.path().join("photo-1.webp");
+        let second = temp_dir.path().join("photo-2.webp");
+        assert_eq!(auto_suffix.resolve(&input, &tokens).unwrap(), Planned::Write(first));
+        assert_eq!(auto_suffix.resolve(&input, &tokens).unwrap(), Planned::Write(second));
+
+        // Never on top of the input, whatever the policy
+        let same_format = Tokens { ext: "png".to_string(), ..tokens.clone() };
+        let suffixed = temp_dir.path().join("photo-1.png");
+        assert_eq!(plan(Collision::Overwrite).resolve(&input, &same_format).unwrap(), Planned::Write(suffixed));
+
+        let broken = OutputPlan { template: "{stem}.{format}".to_string(), ..OutputPlan::default() };
+        assert!(broken.resolve(&input, &tokens).is_err());
+
+        // Braces in the substituted values are fine
+        let braced = Tokens { stem: "scan{1}".to_string(), ..tokens.clone() };
+        let scan = temp_dir.path().join("scan{1}.webp");
+        assert_eq!(plan(Collision::Skip).resolve(&input, &braced).unwrap(), Planned::Write(scan));
+    }
+
+    #[test]
+    fn test_write_atomic() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("photo.png");
+        write_atomic(&path, b"first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        // No temporary file is left behind
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+
+        let missing = temp_dir.path().join("missing/photo.png");
+        assert!(write_atomic(&missing, b"lost").is_err());
+        assert!(!missing.exists());
+    }
+
+    #[test]
+    fn test_ensure_not_source() {
+        let temp_dir = tempdir().unwrap();
+        let input = temp_dir.path().join("photo.png");
+        fs::write(&input, b"").unwrap();
+        let dotted = temp_dir.path().join(".").join("photo.png");
+        assert!(ensure_not_source(&input, &input).is_err());
+        assert!(ensure_not_source(&input, &dotted).is_err());
+        assert!(ensure_not_source(&input, &temp_dir.path().join("photo.webp")).is_ok());
+    }
+
+    #[test]
+    fn test_mirror_input_tree() {
+        let temp_dir = tempdir().unwrap();
+        let inputs = [PathBuf::from("shoot/day1/a.jpg"), PathBuf::from("shoot/day2/raw/b.jpg")];
+        let plan = OutputPlan {
+            dir: Some(temp_dir.path().to_path_buf()),
+            mirror: true,
+            ..OutputPlan::default()
+        }.for_inputs(&inputs);
+
+        let planned = plan.resolve(&inputs[1], &tokens(&inputs[1])).unwrap();
+        assert_eq!(planned, Planned::Write(temp_dir.path().join("day2/raw/b.webp")));
+        assert!(temp_dir.path().join("day2/raw").is_dir());
+
+        let flat = OutputPlan { mirror: false, ..plan };
+        let planned = flat.resolve(&inputs[0], &tokens(&inputs[0])).unwrap();
+        assert_eq!(planned, Planned::Write(temp_dir.path().join("a.webp")));
+    }
+}
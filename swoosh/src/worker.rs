@@ -0,0 +1,519 @@
+use std::{ collections::HashMap, path::{ Path, PathBuf }, sync::Arc, thread };
+
+use image::{ DynamicImage, Frame, ImageDecoder, ImageFormat, ImageReader, ImageResult };
+use tokio::{
+    sync::{ mpsc::{ self, UnboundedSender }, Semaphore },
+    task::{ JoinHandle, JoinSet },
+};
+use tracing::{ error, info, warn };
+
+use crate::{
+    action::Action,
+    components::{
+        image_processing::{
+            animation,
+            compress::{ write_image, EncodeOptions },
+            metadata::read_metadata,
+            pipeline::Pipeline,
+            transform::AlphaPolicy,
+        },
+        value::ImageStatus,
+    },
+    output::{ ensure_not_source, write_atomic, OutputPlan, Planned, Tokens },
+};
+
+/// Memory budget used when neither the config nor the caller sets one.
+pub const DEFAULT_MEMORY_BUDGET_MB: u32 = 2048;
+
+/// A queued `ImageList` entry handed to the worker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Job {
+    /// Position in the list when queued, the `{index}` of the output name.
+    pub index: usize,
+    /// [`ImageInfo::id`](crate::components::value::ImageInfo::id) of the row the job reports to.
+    pub id: usize,
+    pub input: PathBuf,
+}
+
+/// How much work the pool is allowed to do at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Number of images converted concurrently.
+    pub jobs: usize,
+    /// Upper bound, in MiB, on the estimated size of all images decoded at the same time.
+    pub memory_budget_mb: u32,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::new(0, 0)
+    }
+}
+
+impl Limits {
+    /// Builds limits where `0` means "pick a default": one job per core and
+    /// [`DEFAULT_MEMORY_BUDGET_MB`].
+    pub fn new(jobs: usize, memory_budget_mb: u32) -> Self {
+        let jobs = if jobs > 0 {
+            jobs
+        } else {
+            thread::available_parallelism().map_or(1, |n| n.get())
+        };
+        let memory_budget_mb = if memory_budget_mb > 0 {
+            memory_budget_mb
+        } else {
+            DEFAULT_MEMORY_BUDGET_MB
+        };
+        Self { jobs, memory_budget_mb }
+    }
+
+    /// Estimated MiB needed to convert `path`, clamped so a single huge image can still run alone.
+    ///
+    /// Reads the file headers, so it belongs on the blocking pool.
+    fn decode_cost_mb(&self, path: &Path) -> u32 {
+        let Ok(bytes) = decoded_size(path) else {
+            return 1; // Unreadable inputs fail fast without decoding anything
+        };
+        // Decoded source plus one working copy for the pipeline output
+        let mb = (bytes * 2).div_ceil(1024 * 1024);
+        mb.clamp(1, u64::from(self.memory_budget_mb)) as u32
+    }
+}
+
+/// Bytes taken by the decoded pixels of `path`: in their own color type and bit depth for still
+/// images, as RGBA8 for every frame of an animation.
+fn decoded_size(path: &Path) -> ImageResult<u64> {
+    let decoder = ImageReader::open(path)?.with_guessed_format()?.into_decoder()?;
+    let frames = animation::frame_count(path)? as u64;
+    if frames == 1 {
+        return Ok(decoder.total_bytes());
+    }
+    let (width, height) = decoder.dimensions();
+    Ok(u64::from(width) * u64::from(height) * 4 * frames)
+}
+
+/// A finished job: where it was written and optional notes for its `ImageList` row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Converted {
+    pub output: PathBuf,
+    pub details: Option<String>,
+    /// Something the output lost, e.g. transparency flattened for a JPEG or an ICC profile for an
+    /// AVIF.
+    pub warning: Option<String>,
+}
+
+impl Converted {
+    fn skipped(output: PathBuf) -> Self {
+        Self { output, details: Some("skipped, output exists".to_string()), warning: None }
+    }
+}
+
+/// Progress reported by [`run`] as jobs move through the pool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Progress {
+    Started(usize),
+    Finished(usize, Result<Converted, String>),
+}
+
+/// Everything the worker needs to turn one input into one output.
+#[derive(Debug, Clone)]
+pub struct ConversionSettings {
+    pub pipeline: Pipeline,
+    /// `None` writes every output in the format of its input, see [`EncodeOptions::for_path`].
+    pub encoding: Option<EncodeOptions>,
+    /// Transparency policy of outputs in the format of their input.
+    pub alpha: AlphaPolicy,
+    pub output: OutputPlan,
+}
+
+impl Default for ConversionSettings {
+    fn default() -> Self {
+        Self {
+            pipeline: Pipeline::new(),
+            encoding: Some(EncodeOptions::new(ImageFormat::Png, 90)),
+            alpha: AlphaPolicy::default(),
+            output: OutputPlan::default(),
+        }
+    }
+}
+
+impl ConversionSettings {
+    /// Decodes and processes `job`, then writes it wherever `output` plans it.
+    ///
+    /// Animated inputs are processed frame by frame, see [`ConversionSettings::convert_animation`].
+    pub fn convert(&self, job: &Job) -> ImageResult<Converted> {
+        // Without size tokens the output is planned before decoding, so skipped inputs stay cheap
+        let planned = (!self.output.uses_dimensions()).then(|| self.plan(job, (0, 0))).transpose()?;
+        if let Some(Planned::Skip(output)) = planned {
+            return Ok(Converted::skipped(output));
+        }
+        if animation::is_animated(&job.input)? {
+            return self.convert_animation(job, planned);
+        }
+        let (img, metadata) = self.pipeline.decode(&job.input)?;
+        let output = match planned.map_or_else(|| self.plan(job, (img.width(), img.height())), Ok)? {
+            Planned::Write(output) => output,
+            Planned::Skip(output) => return Ok(Converted::skipped(output)),
+        };
+        let encoding = self.encoding_for(&job.input)?;
+        let report = write_image(&img, &metadata, &output, &encoding)?;
+        let details = encoding.target_size.map(|target| report.summary(Some(target)));
+        let warnings: Vec<String> = [
+            report.flattened.then(|| format!("transparency flattened onto {}", encoding.alpha)),
+            metadata.filtered(encoding.metadata).dropped_by(encoding.format),
+        ]
+            .into_iter()
+            .flatten()
+            .collect();
+        let warning = (!warnings.is_empty()).then(|| warnings.join(", "));
+        Ok(Converted { output, details, warning })
+    }
+
+    /// Runs the pipeline on every frame of an animated `job` and writes an animation when the
+    /// output format has one, the first frame with a warning otherwise.
+    ///
+    /// `planned` is the output when it was already planned before decoding.
+    fn convert_animation(&self, job: &Job, planned: Option<Planned>) -> ImageResult<Converted> {
+        let frames = animation::apply(&self.pipeline, animation::decode_frames(&job.input)?)?;
+        let output = match planned.map_or_else(|| self.plan(job, frames[0].buffer().dimensions()), Ok)? {
+            Planned::Write(output) => output,
+            Planned::Skip(output) => return Ok(Converted::skipped(output)),
+        };
+        let encoding = self.encoding_for(&job.input)?;
+        let metadata = read_metadata(&job.input)?.filtered(encoding.metadata);
+        let count = frames.len();
+        if animation::supports_animation(encoding.format) {
+            let encoded = animation::encode_animation(&frames, &encoding)?;
+            let encoded = metadata.embed(encoded, encoding.format, frames[0].buffer().dimensions())?;
+            write_atomic(&output, &encoded)?;
+            let details = Some(format!("{count} frames, {}", bytesize::ByteSize(encoded.len() as u64)));
+            return Ok(Converted { output, details, warning: metadata.dropped_by(encoding.format) });
+        }
+        let first = frames.into_iter().next().map(Frame::into_buffer).unwrap_or_default();
+        let report = write_image(&DynamicImage::ImageRgba8(first), &metadata, &output, &encoding)?;
+        let warnings: Vec<String> = [
+            Some(format!("kept the first of {count} frames")),
+            report.flattened.then(|| format!("transparency flattened onto {}", encoding.alpha)),
+            metadata.dropped_by(encoding.format),
+        ]
+            .into_iter()
+            .flatten()
+            .collect();
+        Ok(Converted { output, details: None, warning: Some(warnings.join(", ")) })
+    }
+
+    /// `encoding`, or the defaults of the format of `input` with the `alpha` policy.
+    fn encoding_for(&self, input: &Path) -> ImageResult<EncodeOptions> {
+        match self.encoding {
+            Some(encoding) => Ok(encoding),
+            None => Ok(EncodeOptions { alpha: self.alpha, ..EncodeOptions::for_path(input)? }),
+        }
+    }
+
+    /// Where `job` goes for an output of `dimensions`, refusing the input itself unless in place.
+    fn plan(&self, job: &Job, dimensions: (u32, u32)) -> ImageResult<Planned> {
+        let extension = match &self.encoding {
+            Some(encoding) => encoding.extension().to_string(),
+            None => job.input.extension().unwrap_or_default().to_string_lossy().into_owned(),
+        };
+        let tokens = Tokens::new(&job.input, &extension, dimensions, job.index + 1);
+        let planned = self.output.resolve(&job.input, &tokens)?;
+        if let Planned::Write(output) = &planned {
+            if !self.encoding.is_some_and(|encoding| encoding.in_place) {
+                ensure_not_source(&job.input, output)?;
+            }
+        }
+        Ok(planned)
+    }
+}
+
+/// Runs `task` over every job on the blocking thread pool within `limits`.
+///
+/// `on_progress` is called from the calling task in completion order; returning `false` stops the
+/// pool and drops every job that has not started yet.
+pub async fn run<F, P>(jobs: Vec<Job>, limits: Limits, task: F, mut on_progress: P)
+    where
+        F: Fn(&Job) -> ImageResult<Converted> + Send + Sync + 'static,
+        P: FnMut(Progress) -> bool
+{
+    let task = Arc::new(task);
+    let slots = Arc::new(Semaphore::new(limits.jobs.max(1)));
+    let memory = Arc::new(Semaphore::new(limits.memory_budget_mb as usize));
+    let (progress_tx, mut progress_rx) = mpsc::unbounded_channel();
+    let mut pool = JoinSet::new();
+
+    for job in jobs {
+        let (task, slots, memory, progress_tx) = (
+            task.clone(),
+            slots.clone(),
+            memory.clone(),
+            progress_tx.clone(),
+        );
+        pool.spawn(async move {
+            let Ok(_slot) = slots.acquire_owned().await else {
+                return;
+            };
+            let input = job.input.clone();
+            let cost = tokio::task::spawn_blocking(move || limits.decode_cost_mb(&input)).await.unwrap_or(1);
+            let Ok(_memory) = memory.acquire_many_owned(cost).await else {
+                return;
+            };
+            let _ = progress_tx.send(Progress::Started(job.index));
+            let index = job.index;
+            let result = match tokio::task::spawn_blocking(move || task(&job)).await {
+                Ok(result) => result.map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            let _ = progress_tx.send(Progress::Finished(index, result));
+        });
+    }
+    drop(progress_tx);
+
+    while let Some(progress) = progress_rx.recv().await {
+        if !on_progress(progress) {
+            pool.abort_all();
+            return;
+        }
+    }
+}
+
+/// Converts `jobs` in the background, reporting through `Action::UpdateImageStatus`.
+///
+/// Every job reports `Converting` when it starts and `Completed` or `Error` when it ends, keyed by
+/// its `id` so rows removed meanwhile do not shift the others. The pool stops once the action
+/// channel is closed.
+pub fn spawn(
+    jobs: Vec<Job>,
+    settings: ConversionSettings,
+    limits: Limits,
+    action_tx: UnboundedSender<Action>
+) -> JoinHandle<()> {
+    spawn_with(jobs, limits, action_tx, move |job| settings.convert(job))
+}
+
+/// Like [`spawn`], for tasks other than a conversion.
+pub fn spawn_with<F>(
+    jobs: Vec<Job>,
+    limits: Limits,
+    action_tx: UnboundedSender<Action>,
+    task: F
+) -> JoinHandle<()>
+    where F: Fn(&Job) -> ImageResult<Converted> + Send + Sync + 'static
+{
+    let ids: HashMap<usize, usize> = jobs.iter().map(|job| (job.index, job.id)).collect();
+    tokio::spawn(async move {
+        run(jobs, limits, task, |progress| {
+            let (Progress::Started(index) | Progress::Finished(index, _)) = &progress;
+            let id = ids[index];
+            let status = match progress {
+                Progress::Started(_) => ImageStatus::Converting,
+                Progress::Finished(index, Ok(converted)) => {
+                    info!("Converted image {index} to {:?}", converted.output);
+                    if let Some(details) = converted.details {
+                        let _ = action_tx.send(Action::UpdateImageDetails { id, details });
+                    }
+                    if let Some(warning) = converted.warning {
+                        warn!("Image {index}: {warning}");
+                        let _ = action_tx.send(Action::UpdateImageWarning { id, warning });
+                    }
+                    ImageStatus::Completed
+                }
+                Progress::Finished(index, Err(e)) => {
+                    error!("Conversion of image {index} failed: {e}");
+                    ImageStatus::Error(e)
+                }
+            };
+            action_tx.send(Action::UpdateImageStatus { id, status }).is_ok()
+        }).await;
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        components::{
+            image_processing::{ metadata::Metadata, pipeline::Operation, transform::RotateOptions },
+            list::ImageList,
+            value::ImageInfo,
+            Component,
+        },
+        output::Collision,
+    };
+
+    #[test]
+    fn test_convert_never_overwrites_input() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("photo.png");
+        std::fs::copy("tests/images/test.png", &input).unwrap();
+        let job = Job { index: 0, id: 0, input: input.clone() };
+
+        let converted = ConversionSettings::default().convert(&job).unwrap();
+        assert_eq!(converted.output, temp_dir.path().join("photo-1.png"));
+        let same_format = ConversionSettings { encoding: None, ..ConversionSettings::default() };
+        assert_eq!(same_format.convert(&job).unwrap().output, temp_dir.path().join("photo-2.png"));
+    }
+
+    #[test]
+    fn test_same_format_follows_alpha_policy() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("photo.jpg");
+        std::fs::copy("tests/images/test.jpg", &input).unwrap();
+        let job = Job { index: 0, id: 0, input };
+        // The corners uncovered by the rotation are transparent
+        let rotate = |alpha| ConversionSettings {
+            pipeline: Pipeline::new().push(Operation::Rotate { degrees: 3.0, options: RotateOptions::default() }),
+            encoding: None,
+            alpha,
+            output: OutputPlan::default(),
+        };
+        let converted = rotate(AlphaPolicy::default()).convert(&job).unwrap();
+        assert_eq!(converted.warning.as_deref(), Some("transparency flattened onto #ffffff"));
+        assert!(rotate(AlphaPolicy::Error).convert(&job).is_err());
+    }
+
+    #[test]
+    fn test_skip_does_not_decode() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("photo.jpg");
+        std::fs::write(&input, b"not a jpeg").unwrap();
+        std::fs::write(temp_dir.path().join("photo.png"), b"").unwrap();
+        let job = Job { index: 0, id: 0, input };
+        let mut output = OutputPlan::default();
+        output.collision = Collision::Skip;
+
+        let skip = ConversionSettings { output: output.clone(), ..ConversionSettings::default() };
+        assert_eq!(skip.convert(&job).unwrap().details.as_deref(), Some("skipped, output exists"));
+        // The size is only known after decoding
+        output.template = "{stem}-{width}.{ext}".to_string();
+        assert!(ConversionSettings { output, ..ConversionSettings::default() }.convert(&job).is_err());
+    }
+
+    #[test]
+    fn test_convert_keeps_animations() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("clip.gif");
+        let frames: Vec<Frame> = [[255, 0, 0, 255], [0, 0, 255, 255]]
+            .into_iter()
+            .map(|color| Frame::new(image::RgbaImage::from_pixel(8, 8, image::Rgba(color))))
+            .collect();
+        animation::write_animation(&frames, &input, &EncodeOptions::new(ImageFormat::Gif, 90)).unwrap();
+        let job = Job { index: 0, id: 0, input };
+
+        let to_webp = ConversionSettings {
+            encoding: Some(EncodeOptions::new(ImageFormat::WebP, 90)),
+            ..ConversionSettings::default()
+        };
+        let converted = to_webp.convert(&job).unwrap();
+        assert_eq!(animation::decode_frames(&converted.output).unwrap().len(), 2);
+        assert_eq!(converted.warning, None);
+
+        let to_jpeg = ConversionSettings {
+            encoding: Some(EncodeOptions::new(ImageFormat::Jpeg, 90)),
+            ..ConversionSettings::default()
+        };
+        let converted = to_jpeg.convert(&job).unwrap();
+        assert_eq!(converted.warning.as_deref(), Some("kept the first of 2 frames"));
+    }
+
+    #[test]
+    fn test_convert_keeps_animation_metadata() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("clip.webp");
+        let frames: Vec<Frame> = [[255, 0, 0, 255], [0, 0, 255, 255]]
+            .into_iter()
+            .map(|color| Frame::new(image::RgbaImage::from_pixel(8, 8, image::Rgba(color))))
+            .collect();
+        let metadata = Metadata { icc: Some(b"not a real profile".to_vec()), ..Metadata::default() };
+        let encoded = animation::encode_animation(&frames, &EncodeOptions::new(ImageFormat::WebP, 90)).unwrap();
+        std::fs::write(&input, metadata.embed(encoded, ImageFormat::WebP, (8, 8)).unwrap()).unwrap();
+        let job = Job { index: 0, id: 0, input };
+
+        let to_png = ConversionSettings {
+            encoding: Some(EncodeOptions::new(ImageFormat::Png, 90)),
+            ..ConversionSettings::default()
+        };
+        let converted = to_png.convert(&job).unwrap();
+        assert_eq!(animation::decode_frames(&converted.output).unwrap().len(), 2);
+        assert_eq!(read_metadata(&converted.output).unwrap().icc, metadata.icc);
+
+        let to_jpeg = ConversionSettings {
+            encoding: Some(EncodeOptions::new(ImageFormat::Jpeg, 90)),
+            ..ConversionSettings::default()
+        };
+        let converted = to_jpeg.convert(&job).unwrap();
+        assert_eq!(converted.warning.as_deref(), Some("kept the first of 2 frames"));
+        assert_eq!(read_metadata(&converted.output).unwrap().icc, metadata.icc);
+    }
+
+    #[test]
+    fn test_decode_cost_is_clamped_to_budget() {
+        let limits = Limits::new(1, 1);
+        assert_eq!(limits.decode_cost_mb(Path::new("tests/images/test.png")), 1);
+        assert_eq!(limits.decode_cost_mb(Path::new("missing.png")), 1);
+    }
+
+    #[test]
+    fn test_decoded_size_counts_color_type_and_frames() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let gray = temp_dir.path().join("gray.png");
+        image::GrayImage::new(100, 50).save(&gray).unwrap();
+        assert_eq!(decoded_size(&gray).unwrap(), 100 * 50);
+        assert_eq!(decoded_size(Path::new("tests/images/test.png")).unwrap(), 529 * 661 * 4);
+
+        let clip = temp_dir.path().join("clip.gif");
+        let frames: Vec<Frame> = (0..3).map(|_| Frame::new(image::RgbaImage::new(100, 50))).collect();
+        animation::write_animation(&frames, &clip, &EncodeOptions::new(ImageFormat::Gif, 90)).unwrap();
+        assert_eq!(decoded_size(&clip).unwrap(), 100 * 50 * 4 * 3);
+    }
+
+    #[tokio::test]
+    async fn test_reports_status_transitions() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("test.jpg");
+        std::fs::copy("tests/images/test.jpg", &input).unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        let jobs = vec![Job { index: 0, id: 0, input }, Job { index: 1, id: 1, input: "missing.png".into() }];
+        spawn(jobs, ConversionSettings::default(), Limits::new(2, 0), tx).await.unwrap();
+
+        let mut statuses = Vec::new();
+        while let Ok(Action::UpdateImageStatus { id, status }) = rx.try_recv() {
+            statuses.push((id, status));
+        }
+        assert_eq!(statuses.len(), 4);
+        assert!(statuses.contains(&(0, ImageStatus::Converting)));
+        assert!(statuses.contains(&(0, ImageStatus::Completed)));
+        assert!(statuses.contains(&(1, ImageStatus::Converting)));
+        assert!(statuses.iter().any(|s| matches!(s, (1, ImageStatus::Error(_)))));
+        assert!(temp_dir.path().join("test.png").exists());
+    }
+
+    #[tokio::test]
+    async fn test_status_follows_rows_removed_while_converting() {
+        let mut list = ImageList::new();
+        list.add_image(ImageInfo::new("tests/images/test.png".into()).unwrap());
+        list.add_image(ImageInfo::new("tests/images/test.jpg".into()).unwrap());
+        let jobs = list.image_data
+            .iter()
+            .enumerate()
+            .map(|(index, image)| Job { index, id: image.id, input: image.path.clone() })
+            .collect();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let task = |job: &Job| Ok(Converted { output: job.input.clone(), details: None, warning: None });
+        spawn_with(jobs, Limits::new(1, 0), tx, task).await.unwrap();
+
+        let second = list.image_data[1].id;
+        while let Ok(action) = rx.try_recv() {
+            let started = action == Action::UpdateImageStatus { id: second, status: ImageStatus::Converting };
+            list.update(action).unwrap();
+            if started {
+                // The first row is deleted between the second one starting and finishing
+                list.remove_image(0);
+            }
+        }
+        assert_eq!(list.image_data.len(), 1);
+        assert_eq!(list.image_data[0].id, second);
+        assert_eq!(list.image_data[0].status, ImageStatus::Completed);
+    }
+}